@@ -0,0 +1,248 @@
+// Live dashboard fed by the snapshot stream, one subscription per subsystem so
+// every event carries fresh values for the section it updates.
+"use strict";
+
+// Number of points kept in the charts
+const HISTORY = 120;
+// Number of processes listed
+const TOP_PROCESSES = 10;
+
+const cpuHistory = [];
+const interfaceHistory = new Map();
+let previousCpu = null;
+let previousInterfaces = null;
+
+function value(field) {
+    return field ? field.value : 0;
+}
+
+function formatBytes(bytes) {
+    const units = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let i = 0;
+    while (bytes >= 1024 && i < units.length - 1) {
+        bytes /= 1024;
+        i++;
+    }
+    return `${bytes.toFixed(i === 0 ? 0 : 1)} ${units[i]}`;
+}
+
+function formatRate(bytesPerSecond) {
+    return `${formatBytes(bytesPerSecond)}/s`;
+}
+
+function push(history, point) {
+    history.push(point);
+    if (history.length > HISTORY) {
+        history.shift();
+    }
+}
+
+function setGauge(id, used, total) {
+    const fill = document.querySelector(`#${id} .fill`);
+    const text = document.getElementById(`${id}-text`);
+    if (!total) {
+        fill.style.width = "0";
+        text.textContent = id === "swap" ? "No swap" : "No data";
+        return;
+    }
+    const ratio = used / total;
+    fill.style.width = `${(ratio * 100).toFixed(1)}%`;
+    fill.className = ratio > 0.9 ? "fill critical" : ratio > 0.75 ? "fill warning" : "fill";
+    text.textContent = `${formatBytes(used)} of ${formatBytes(total)} (${(ratio * 100).toFixed(1)}%)`;
+}
+
+// Draws series of values between 0 and `max` (the largest value when omitted)
+function drawChart(canvas, series, max) {
+    const scale = window.devicePixelRatio || 1;
+    const width = canvas.clientWidth;
+    const height = canvas.clientHeight;
+    canvas.width = width * scale;
+    canvas.height = height * scale;
+    const context = canvas.getContext("2d");
+    context.scale(scale, scale);
+    context.clearRect(0, 0, width, height);
+
+    const top = max || Math.max(1, ...series.flatMap((s) => s.points));
+    context.strokeStyle = "#eeeeee";
+    context.beginPath();
+    context.moveTo(0, height - 0.5);
+    context.lineTo(width, height - 0.5);
+    context.stroke();
+
+    for (const { points, color } of series) {
+        context.strokeStyle = color;
+        context.lineWidth = 1.5;
+        context.beginPath();
+        points.forEach((point, i) => {
+            const x = (i + HISTORY - points.length) * (width / (HISTORY - 1));
+            const y = height - (point / top) * (height - 2) - 1;
+            if (i === 0) {
+                context.moveTo(x, y);
+            } else {
+                context.lineTo(x, y);
+            }
+        });
+        context.stroke();
+    }
+    return top;
+}
+
+// Share of busy time per core, from the time spent in each mode since the previous
+// snapshot. Snapshots whose times didn't grow, such as the first one, only set the
+// baseline.
+function updateCpu(cores) {
+    const times = cores.map((core) => {
+        const busy = value(core.user) + value(core.system) + value(core.nice);
+        return { busy, total: busy + value(core.idle) };
+    });
+    const previous = previousCpu;
+    // Times restart from zero when the machine reboots
+    if (!previous || previous.length !== times.length ||
+        times.some((time, i) => time.total < previous[i].total)) {
+        previousCpu = times;
+        return;
+    }
+    if (times.some((time, i) => time.total === previous[i].total)) {
+        return;
+    }
+    previousCpu = times;
+    const usage = times.map((time, i) => (time.busy - previous[i].busy) / (time.total - previous[i].total));
+
+    const average = usage.reduce((sum, u) => sum + u, 0) / Math.max(1, usage.length);
+    push(cpuHistory, average * 100);
+    drawChart(document.getElementById("cpu-history"), [{ points: cpuHistory, color: "#0066cc" }], 100);
+
+    const container = document.getElementById("cores");
+    container.replaceChildren(...cores.map((core, i) => {
+        const element = document.createElement("div");
+        element.className = "core";
+        const percent = (usage[i] * 100).toFixed(0);
+        element.innerHTML = `<div>Core ${core.core}: ${percent}%</div>` +
+            `<div class="gauge"><div class="fill" style="width: ${percent}%"></div></div>`;
+        return element;
+    }));
+}
+
+function updateProcesses(processes) {
+    const rows = processes
+        .slice()
+        .sort((a, b) => value(b.cpu_usage) - value(a.cpu_usage) || value(b.memory) - value(a.memory))
+        .slice(0, TOP_PROCESSES)
+        .map((process) => {
+            const row = document.createElement("tr");
+            const name = document.createElement("td");
+            name.textContent = process.name;
+            const cpu = document.createElement("td");
+            cpu.className = "number";
+            cpu.textContent = `${value(process.cpu_usage).toFixed(1)}%`;
+            const memory = document.createElement("td");
+            memory.className = "number";
+            memory.textContent = formatBytes(value(process.memory));
+            row.append(name, cpu, memory);
+            return row;
+        });
+    document.getElementById("processes").replaceChildren(...rows);
+}
+
+function interfaceElement(name) {
+    let element = document.getElementById(`interface-${name}`);
+    if (!element) {
+        element = document.createElement("div");
+        element.id = `interface-${name}`;
+        element.className = "interface";
+        element.innerHTML = `<h3></h3><canvas height="80"></canvas><div class="legend"></div>`;
+        element.querySelector("h3").textContent = name;
+        document.getElementById("interfaces").append(element);
+    }
+    return element;
+}
+
+// Throughput from the bytes counted since the previous snapshot
+function updateInterfaces(interfaces, collectedAt) {
+    const container = document.getElementById("interfaces");
+    if (!previousInterfaces) {
+        container.replaceChildren();
+    }
+    const current = new Map(interfaces.map((i) => [i.interface, {
+        received: value(i.received),
+        transmitted: value(i.transmitted),
+        at: collectedAt,
+    }]));
+
+    for (const [name, now] of current) {
+        const previous = previousInterfaces && previousInterfaces.get(name);
+        if (!previous || now.at <= previous.at) {
+            continue;
+        }
+        const seconds = now.at - previous.at;
+        // Counters restart from zero when the interface goes away
+        const received = Math.max(0, now.received - previous.received) / seconds;
+        const transmitted = Math.max(0, now.transmitted - previous.transmitted) / seconds;
+
+        if (!interfaceHistory.has(name)) {
+            interfaceHistory.set(name, { received: [], transmitted: [] });
+        }
+        const history = interfaceHistory.get(name);
+        push(history.received, received);
+        push(history.transmitted, transmitted);
+
+        const element = interfaceElement(name);
+        const top = drawChart(element.querySelector("canvas"), [
+            { points: history.received, color: "#2a9d8f" },
+            { points: history.transmitted, color: "#e76f51" },
+        ]);
+        element.querySelector(".legend").innerHTML =
+            `<span class="received">▼ ${formatRate(received)}</span> ` +
+            `<span class="transmitted">▲ ${formatRate(transmitted)}</span> ` +
+            `<span class="muted">(scale ${formatRate(top)})</span>`;
+    }
+    previousInterfaces = current;
+}
+
+const subscriptions = new Map();
+
+function updateStatus() {
+    const status = document.getElementById("status");
+    const states = [...subscriptions.values()].map((source) => source.readyState);
+    if (states.every((state) => state === EventSource.OPEN)) {
+        status.textContent = "Live";
+        status.className = "status live";
+    } else if (states.some((state) => state === EventSource.CLOSED)) {
+        status.textContent = "Disconnected";
+        status.className = "status down";
+    } else {
+        status.textContent = "Reconnecting…";
+        status.className = "status";
+    }
+}
+
+function subscribe(collectors, update) {
+    const query = collectors.map((name) => `collect[]=${name}`).join("&");
+    const source = new EventSource(`/api/v1/stream?${query}`);
+    source.addEventListener("snapshot", (event) => update(JSON.parse(event.data)));
+    source.addEventListener("open", updateStatus);
+    source.addEventListener("error", updateStatus);
+    subscriptions.set(query, source);
+}
+
+subscribe(["cpu", "memory", "swap"], (snapshot) => {
+    if (snapshot.cpu) {
+        updateCpu(snapshot.cpu);
+    }
+    const memory = snapshot.memory;
+    setGauge("memory", memory ? value(memory.used) : 0, memory ? value(memory.total) : 0);
+    const swap = snapshot.swap;
+    setGauge("swap", swap ? value(swap.used) : 0, swap ? value(swap.total) : 0);
+});
+
+subscribe(["process"], (snapshot) => {
+    if (snapshot.processes) {
+        updateProcesses(snapshot.processes);
+    }
+});
+
+subscribe(["network"], (snapshot) => {
+    if (snapshot.interfaces && snapshot.collected_at) {
+        updateInterfaces(snapshot.interfaces, snapshot.collected_at);
+    }
+});
@@ -0,0 +1,28 @@
+use std::env;
+use std::process::Command;
+
+/// Runs a command and returns its trimmed output, `unknown` when it fails
+fn output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn main() {
+    // Build information exported by simon_exporter_build_info
+    let commit = output("git", &["rev-parse", "--short", "HEAD"]);
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = output(&rustc, &["--version"]);
+
+    println!("cargo:rustc-env=SIMON_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=SIMON_RUSTC_VERSION={}", rustc_version);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+    // Branch heads end up here once git packs its refs
+    println!("cargo:rerun-if-changed=.git/packed-refs");
+}
@@ -0,0 +1,335 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prometheus::proto::{Metric, MetricFamily, MetricType};
+use serde_json::{json, Map, Value};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+use tokio::time::{Interval, MissedTickBehavior};
+use tracing::{debug, error};
+
+use crate::exposition::Filter;
+use crate::openmetrics;
+use crate::state::{AppState, Snapshot};
+
+/// A stream subscriber, receiving the snapshot of every completed collection cycle
+/// as JSON, limited to the collectors of its filter
+pub struct Subscription {
+    /// The snapshot at the time of subscribing, sent first
+    current: Option<Arc<Snapshot>>,
+    cycles: broadcast::Receiver<Arc<Snapshot>>,
+    closing: watch::Receiver<bool>,
+    filter: Filter,
+    state: Arc<AppState>,
+    /// In scrape mode, when the subscriber collects the collectors of its filter, as
+    /// no background task does
+    poll: Option<Interval>,
+}
+
+impl Subscription {
+    pub fn new(state: Arc<AppState>, filter: Filter) -> Self {
+        let current = state.snapshot();
+        let poll = state.scrape_interval().map(|period| {
+            let mut poll = tokio::time::interval(period);
+            poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            poll
+        });
+        Subscription {
+            current: current.collected_at.is_some().then_some(current),
+            cycles: state.subscribe(),
+            closing: state.closing(),
+            filter,
+            state,
+            poll,
+        }
+    }
+
+    /// Waits for the next snapshot updating a collector of the filter, `None` once
+    /// simon shuts down.
+    ///
+    /// A subscriber too slow to keep up skips to the latest snapshots.
+    pub async fn next(&mut self) -> Option<Value> {
+        if let Some(current) = self.current.take() {
+            return Some(snapshot(&current, &self.filter));
+        }
+        loop {
+            let collect = tokio::select! {
+                _ = self.closing.wait_for(|closing| *closing) => return None,
+                _ = tick(&mut self.poll) => true,
+                cycle = self.cycles.recv() => match cycle {
+                    Ok(cycle) if cycle.updated.iter().any(|name| self.filter.includes(name)) => {
+                        return Some(snapshot(&cycle, &self.filter));
+                    }
+                    Ok(_) => false,
+                    Err(RecvError::Lagged(skipped)) => {
+                        debug!("Stream subscriber skipped {} snapshots", skipped);
+                        false
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+            };
+            // Published like any other run, so the snapshot arrives as a cycle
+            if collect {
+                if let Err(e) = self.state.collect_for_scrape(&self.filter).await {
+                    error!("Failed to collect metrics for a stream: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Waits for the next tick of `poll`, forever when there is none
+async fn tick(poll: &mut Option<Interval>) {
+    match poll {
+        Some(poll) => {
+            poll.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// The latest collected values as structured JSON, for consumers that don't speak
+/// the Prometheus formats.
+///
+/// Every value comes with its unit when it has one, e.g.
+/// `{"value": 6305947648, "unit": "bytes"}`. Sections of disabled collectors, or of
+/// collectors left out by the filter, are null.
+pub fn snapshot(snapshot: &Snapshot, filter: &Filter) -> Value {
+    let families = &snapshot.select(filter);
+    json!({
+        "collected_at": collected_at(snapshot),
+        "cpu": grouped(families, "cpu", "core"),
+        "memory": scalars(families, "memory"),
+        "swap": scalars(families, "swap"),
+        "processes": grouped(families, "process", "name"),
+        "interfaces": grouped(families, "network", "interface"),
+    })
+}
+
+/// Unix time in seconds of the collection, null before the first one
+fn collected_at(snapshot: &Snapshot) -> Option<f64> {
+    let collected_at = SystemTime::now().checked_sub(snapshot.collected_at?.elapsed())?;
+    collected_at
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|time| time.as_secs_f64())
+}
+
+/// The unlabelled values of a collector, keyed by metric name without the unit
+fn scalars(families: &[MetricFamily], subsystem: &str) -> Option<Value> {
+    let mut values = Map::new();
+    for (key, family) in subsystem_families(families, subsystem) {
+        for metric in family.get_metric() {
+            if let Some(value) = value(family, metric) {
+                values.insert(key.clone(), value);
+            }
+        }
+    }
+    (!values.is_empty()).then_some(Value::Object(values))
+}
+
+/// The values of a collector grouped by `label`, one object per label value.
+///
+/// A value with further labels is keyed by them, e.g. the `mode` of CPU times,
+/// otherwise by its metric name without the unit.
+fn grouped(families: &[MetricFamily], subsystem: &str, label: &str) -> Option<Value> {
+    let mut groups: HashMap<String, Map<String, Value>> = HashMap::new();
+    for (key, family) in subsystem_families(families, subsystem) {
+        for metric in family.get_metric() {
+            let Some(group) = metric.get_label().iter().find(|pair| pair.name() == label) else {
+                continue;
+            };
+            let Some(value) = value(family, metric) else {
+                continue;
+            };
+            let others: Vec<&str> = metric
+                .get_label()
+                .iter()
+                .filter(|pair| pair.name() != label)
+                .map(|pair| pair.value())
+                .collect();
+            let key = if others.is_empty() {
+                key.clone()
+            } else {
+                others.join("_")
+            };
+            groups
+                .entry(group.value().to_string())
+                .or_default()
+                .insert(key, value);
+        }
+    }
+    if groups.is_empty() {
+        return None;
+    }
+
+    // Numbered groups such as CPU cores sort by number
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by_cached_key(|(group, _)| (group.parse::<u64>().ok(), group.clone()));
+    let groups = groups
+        .into_iter()
+        .map(|(group, values)| {
+            let mut object = Map::new();
+            object.insert(label.to_string(), Value::String(group));
+            object.extend(values);
+            Value::Object(object)
+        })
+        .collect();
+    Some(Value::Array(groups))
+}
+
+/// The families of a collector with their key, the name without namespace,
+/// subsystem, `_total` suffix and unit
+fn subsystem_families<'a>(
+    families: &'a [MetricFamily],
+    subsystem: &str,
+) -> BTreeMap<String, &'a MetricFamily> {
+    let prefix = format!("simon_{}_", subsystem);
+    families
+        .iter()
+        .filter_map(|family| {
+            let name = family.name().strip_prefix(&prefix)?;
+            let name = name.strip_suffix("_total").unwrap_or(name);
+            let key = match openmetrics::unit(name) {
+                Some(unit) => name.strip_suffix(&format!("_{}", unit)).unwrap_or(name),
+                None => name,
+            };
+            Some((key.to_string(), family))
+        })
+        .collect()
+}
+
+/// A counter, gauge or untyped value with its unit
+fn value(family: &MetricFamily, metric: &Metric) -> Option<Value> {
+    let value = match family.get_field_type() {
+        MetricType::COUNTER => metric.get_counter().value(),
+        MetricType::GAUGE => metric.get_gauge().value(),
+        MetricType::UNTYPED => metric.untyped.value(),
+        MetricType::HISTOGRAM | MetricType::SUMMARY => return None,
+    };
+    let name = family.name();
+    let unit = openmetrics::unit(name.strip_suffix("_total").unwrap_or(name));
+    Some(match unit {
+        Some(unit) => json!({ "value": value, "unit": unit }),
+        None => json!({ "value": value }),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{CounterVec, Gauge, GaugeVec, Opts, Registry};
+
+    use super::*;
+
+    /// Registers metrics named like those of the cpu, memory, network and process
+    /// collectors and gathers them into a snapshot
+    fn collected() -> Snapshot {
+        let registry = Registry::new();
+        let mut owners = HashMap::new();
+        let mut register =
+            |collector: &'static str, metric: Box<dyn prometheus::core::Collector>| {
+                for desc in metric.desc() {
+                    owners.insert(desc.fq_name.clone(), collector);
+                }
+                registry.register(metric).unwrap();
+            };
+        let opts = |subsystem: &str, name: &str| {
+            Opts::new(name, "Help")
+                .namespace("simon")
+                .subsystem(subsystem)
+        };
+
+        let cpu = CounterVec::new(opts("cpu", "cpu_seconds_total"), &["core", "mode"]).unwrap();
+        for (mode, seconds) in [
+            ("user", 10.0),
+            ("system", 5.0),
+            ("nice", 0.5),
+            ("idle", 100.0),
+        ] {
+            cpu.with_label_values(&["0", mode]).inc_by(seconds);
+        }
+        register("cpu", Box::new(cpu));
+
+        for (name, bytes) in [("used_bytes", 600.0), ("total_bytes", 1000.0)] {
+            let gauge = Gauge::with_opts(opts("memory", name)).unwrap();
+            gauge.set(bytes);
+            register("memory", Box::new(gauge));
+        }
+
+        for (name, bytes) in [
+            ("received_bytes_total", 2048.0),
+            ("transmitted_bytes_total", 1024.0),
+        ] {
+            let counter = CounterVec::new(opts("network", name), &["interface"]).unwrap();
+            counter.with_label_values(&["eth0"]).inc_by(bytes);
+            register("network", Box::new(counter));
+        }
+
+        for (name, value) in [("cpu_usage_percentage", 12.5), ("memory_bytes", 4096.0)] {
+            let gauge = GaugeVec::new(opts("process", name), &["name"]).unwrap();
+            gauge.with_label_values(&["simon"]).set(value);
+            register("process", Box::new(gauge));
+        }
+
+        Snapshot {
+            families: registry.gather(),
+            collected_at: Some(std::time::Instant::now()),
+            owners: Arc::new(owners),
+            ..Snapshot::default()
+        }
+    }
+
+    #[test]
+    fn snapshot_groups_values_with_their_units() {
+        let filter = Filter::from_query(Some("exclude[]=network")).unwrap();
+        let json = snapshot(&collected(), &filter);
+
+        assert!(json["collected_at"].is_f64());
+        assert_eq!(
+            json["cpu"],
+            json!([{
+                "core": "0",
+                "user": {"value": 10.0, "unit": "seconds"},
+                "system": {"value": 5.0, "unit": "seconds"},
+                "nice": {"value": 0.5, "unit": "seconds"},
+                "idle": {"value": 100.0, "unit": "seconds"},
+            }])
+        );
+        assert_eq!(
+            json["memory"],
+            json!({
+                "used": {"value": 600.0, "unit": "bytes"},
+                "total": {"value": 1000.0, "unit": "bytes"},
+            })
+        );
+        assert_eq!(
+            json["processes"],
+            json!([{
+                "name": "simon",
+                "cpu_usage": {"value": 12.5, "unit": "percentage"},
+                "memory": {"value": 4096.0, "unit": "bytes"},
+            }])
+        );
+        // Filtered out, and collected by nobody
+        assert_eq!(json["interfaces"], Value::Null);
+        assert_eq!(json["swap"], Value::Null);
+    }
+
+    #[test]
+    fn snapshot_includes_interfaces_unless_filtered_out() {
+        let json = snapshot(&collected(), &Filter::default());
+        assert_eq!(
+            json["interfaces"],
+            json!([{
+                "interface": "eth0",
+                "received": {"value": 2048.0, "unit": "bytes"},
+                "transmitted": {"value": 1024.0, "unit": "bytes"},
+            }])
+        );
+
+        let json = snapshot(&Snapshot::default(), &Filter::default());
+        assert_eq!(json["collected_at"], Value::Null);
+        assert_eq!(json["cpu"], Value::Null);
+    }
+}
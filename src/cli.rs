@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use tracing::Level;
+
+/// System metrics exporter for Prometheus
+#[derive(Debug, Parser)]
+#[command(name = "simon", version)]
+pub struct Cli {
+    /// Configuration file
+    #[arg(short, long, global = true, env = "SIMON_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Log level: error, warn, info, debug or trace
+    #[arg(long, global = true, env = "SIMON_LOG_LEVEL", default_value = "info")]
+    pub log_level: Level,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Serve metrics over HTTP (the default)
+    Serve,
+    /// Collect once, print the metrics to stdout and exit
+    Once,
+    /// Validate the configuration and exit
+    CheckConfig,
+    /// List the available collectors and whether they are enabled
+    ListCollectors,
+    /// Print version information
+    Version,
+}
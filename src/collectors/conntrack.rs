@@ -0,0 +1,314 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+
+use prometheus::{Gauge, GaugeVec, Opts};
+use sysinfo::System;
+
+use super::{CollectError, Collector};
+
+const CONNTRACK_COUNT_PATH: &str = "/proc/sys/net/netfilter/nf_conntrack_count";
+const CONNTRACK_MAX_PATH: &str = "/proc/sys/net/netfilter/nf_conntrack_max";
+const CONNTRACK_TABLE_PATH: &str = "/proc/net/nf_conntrack";
+
+/// A single tracked connection, as listed in `/proc/net/nf_conntrack`
+#[derive(Debug)]
+pub struct Connection {
+    /// Layer 4 protocol name (tcp, udp, icmp, ...)
+    pub protocol: String,
+    /// TCP state, `None` for stateless protocols
+    pub state: Option<String>,
+    /// Source address of the original direction
+    pub source: Option<IpAddr>,
+}
+
+/// Parses one line of `/proc/net/nf_conntrack`.
+///
+/// Lines look like
+/// `ipv4 2 tcp 6 431999 ESTABLISHED src=192.168.1.10 dst=1.1.1.1 sport=50000 dport=443 ...`
+/// where the state column is only present for TCP.
+pub fn parse_connection(line: &str) -> Option<Connection> {
+    let mut fields = line.split_whitespace();
+    // Skip the layer 3 name and number
+    fields.nth(1)?;
+    let protocol = fields.next()?.to_string();
+    // Skip the layer 4 number and the timeout
+    fields.nth(1)?;
+
+    let mut state = None;
+    let mut source = None;
+    for field in fields {
+        match field.split_once('=') {
+            Some(("src", value)) => {
+                // Only the first src= belongs to the original direction
+                source = value.parse().ok();
+                break;
+            }
+            Some(_) => {}
+            None if state.is_none() && !field.starts_with('[') => {
+                state = Some(field.to_string());
+            }
+            None => {}
+        }
+    }
+
+    Some(Connection {
+        protocol,
+        state,
+        source,
+    })
+}
+
+/// Whether an address belongs to a local network (RFC 1918, link-local or IPv6 ULA)
+pub fn is_lan_address(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => v4.is_private() || v4.is_link_local(),
+        IpAddr::V6(v6) => {
+            let first = v6.segments()[0];
+            (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+fn read_value(path: &str) -> io::Result<u64> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Collector for the connection tracking metrics
+pub struct ConntrackCollector {
+    /// Number of entries in the conntrack table
+    entries: Gauge,
+    /// Maximum number of entries in the conntrack table
+    entries_limit: Gauge,
+
+    /// Live connections per protocol and TCP state
+    connections: GaugeVec,
+    /// Live connections per LAN source address
+    device_connections: GaugeVec,
+}
+
+impl ConntrackCollector {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let entries_opts = Opts::new("entries", "Number of entries in the conntrack table")
+            .namespace("simon")
+            .subsystem("conntrack");
+        let entries = Gauge::with_opts(entries_opts)?;
+
+        let entries_limit_opts = Opts::new(
+            "entries_limit",
+            "Maximum number of entries in the conntrack table",
+        )
+        .namespace("simon")
+        .subsystem("conntrack");
+        let entries_limit = Gauge::with_opts(entries_limit_opts)?;
+
+        let connections_opts =
+            Opts::new("connections", "Live connections per protocol and TCP state")
+                .namespace("simon")
+                .subsystem("conntrack");
+        let connections = GaugeVec::new(connections_opts, &["protocol", "state"])?;
+
+        let device_connections_opts = Opts::new(
+            "device_connections",
+            "Live connections per LAN source address",
+        )
+        .namespace("simon")
+        .subsystem("conntrack");
+        let device_connections = GaugeVec::new(device_connections_opts, &["ip"])?;
+
+        Ok(ConntrackCollector {
+            entries,
+            entries_limit,
+            connections,
+            device_connections,
+        })
+    }
+
+    /// Reads the conntrack table size and entries from procfs and updates the metrics
+    fn update_conntrack_metrics(&mut self) -> io::Result<()> {
+        self.entries.set(read_value(CONNTRACK_COUNT_PATH)? as f64);
+        let limit = read_value(CONNTRACK_MAX_PATH)?;
+        self.entries_limit.set(limit as f64);
+
+        // The table itself is only exposed when nf_conntrack_procfs is enabled
+        let table = match fs::read_to_string(CONNTRACK_TABLE_PATH) {
+            Ok(table) => table,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        self.update_connections(table.lines().filter_map(parse_connection));
+
+        Ok(())
+    }
+
+    fn update_connections(&self, connections: impl Iterator<Item = Connection>) {
+        let mut by_state: HashMap<(String, String), u64> = HashMap::new();
+        let mut by_device: HashMap<IpAddr, u64> = HashMap::new();
+
+        for connection in connections {
+            let state = connection.state.unwrap_or_default();
+            *by_state.entry((connection.protocol, state)).or_default() += 1;
+
+            if let Some(source) = connection.source.filter(is_lan_address) {
+                *by_device.entry(source).or_default() += 1;
+            }
+        }
+
+        // Connections come and go, so drop label sets that are no longer present
+        self.connections.reset();
+        for ((protocol, state), count) in by_state {
+            self.connections
+                .with_label_values(&[protocol.as_str(), state.as_str()])
+                .set(count as f64);
+        }
+
+        self.device_connections.reset();
+        for (ip, count) in by_device {
+            self.device_connections
+                .with_label_values(&[ip.to_string().as_str()])
+                .set(count as f64);
+        }
+    }
+}
+
+impl Collector for ConntrackCollector {
+    fn name(&self) -> &'static str {
+        "conntrack"
+    }
+
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+        vec![
+            Box::new(self.entries.clone()),
+            Box::new(self.entries_limit.clone()),
+            Box::new(self.connections.clone()),
+            Box::new(self.device_connections.clone()),
+        ]
+    }
+
+    fn collect(&mut self, _system: &System) -> Result<(), CollectError> {
+        Ok(self.update_conntrack_metrics()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &str = include_str!("../../fixtures/conntrack/nf_conntrack");
+
+    #[test]
+    fn parses_tcp_connection_with_state() {
+        let connection = parse_connection(TABLE.lines().next().unwrap()).unwrap();
+        assert_eq!(connection.protocol, "tcp");
+        assert_eq!(connection.state.as_deref(), Some("ESTABLISHED"));
+        assert_eq!(connection.source, Some("192.168.1.10".parse().unwrap()));
+    }
+
+    #[test]
+    fn parses_stateless_protocols() {
+        let lines: Vec<&str> = TABLE.lines().collect();
+
+        let udp = parse_connection(lines[3]).unwrap();
+        assert_eq!(udp.protocol, "udp");
+        assert_eq!(udp.state, None);
+        assert_eq!(udp.source, Some("192.168.1.23".parse().unwrap()));
+
+        let icmp = parse_connection(lines[5]).unwrap();
+        assert_eq!(icmp.protocol, "icmp");
+        assert_eq!(icmp.state, None);
+    }
+
+    #[test]
+    fn ignores_flags_and_reply_direction() {
+        let lines: Vec<&str> = TABLE.lines().collect();
+
+        // [UNREPLIED] sits between the two directions
+        let unreplied = parse_connection(lines[2]).unwrap();
+        assert_eq!(unreplied.state.as_deref(), Some("SYN_SENT"));
+        assert_eq!(unreplied.source, Some("192.168.1.23".parse().unwrap()));
+
+        let unreplied_udp = parse_connection(lines[4]).unwrap();
+        assert_eq!(unreplied_udp.state, None);
+        assert_eq!(unreplied_udp.source, Some("203.0.113.7".parse().unwrap()));
+    }
+
+    #[test]
+    fn parses_ipv6_connections() {
+        let lines: Vec<&str> = TABLE.lines().collect();
+
+        let tcp = parse_connection(lines[6]).unwrap();
+        assert_eq!(tcp.protocol, "tcp");
+        assert_eq!(tcp.state.as_deref(), Some("ESTABLISHED"));
+        assert_eq!(tcp.source, Some("fd00::10".parse().unwrap()));
+
+        let udp = parse_connection(lines[7]).unwrap();
+        assert_eq!(udp.source, Some("fe80::1c2:3ff:fe04:506".parse().unwrap()));
+    }
+
+    #[test]
+    fn rejects_truncated_lines() {
+        assert!(parse_connection("").is_none());
+        assert!(parse_connection("ipv4 2 tcp").is_none());
+    }
+
+    #[test]
+    fn recognises_lan_addresses() {
+        let lan = [
+            "192.168.1.10",
+            "10.0.0.1",
+            "172.16.5.4",
+            "169.254.1.1",
+            "fd00::10",
+            "fe80::1",
+        ];
+        for addr in lan {
+            assert!(is_lan_address(&addr.parse().unwrap()), "{}", addr);
+        }
+        let wan = ["1.1.1.1", "203.0.113.7", "2606:4700::1111", "ff02::fb"];
+        for addr in wan {
+            assert!(!is_lan_address(&addr.parse().unwrap()), "{}", addr);
+        }
+    }
+
+    #[test]
+    fn counts_connections_per_state_and_device() {
+        let collector = ConntrackCollector::new().unwrap();
+        collector.update_connections(TABLE.lines().filter_map(parse_connection));
+
+        let tcp = |state| {
+            collector
+                .connections
+                .with_label_values(&["tcp", state])
+                .get()
+        };
+        assert_eq!(tcp("ESTABLISHED"), 2.0);
+        assert_eq!(tcp("TIME_WAIT"), 1.0);
+        assert_eq!(
+            collector.connections.with_label_values(&["udp", ""]).get(),
+            3.0
+        );
+
+        let device = |ip| collector.device_connections.with_label_values(&[ip]).get();
+        assert_eq!(device("192.168.1.10"), 3.0);
+        assert_eq!(device("192.168.1.23"), 2.0);
+        assert_eq!(device("fd00::10"), 1.0);
+    }
+
+    #[test]
+    fn reads_proc_values() {
+        let path = std::env::temp_dir().join(format!("simon-conntrack-{}", std::process::id()));
+        fs::write(&path, "262144\n").unwrap();
+        assert_eq!(read_value(path.to_str().unwrap()).unwrap(), 262144);
+
+        fs::write(&path, "not a number\n").unwrap();
+        let error = read_value(path.to_str().unwrap()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_file(&path).unwrap();
+        assert!(read_value(path.to_str().unwrap()).is_err());
+    }
+}
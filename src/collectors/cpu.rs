@@ -0,0 +1,59 @@
+use prometheus::{CounterVec, Opts};
+use sysinfo::{Cpu, CpuRefreshKind, RefreshKind, System};
+
+use super::{CollectError, Collector};
+
+/// Collector for the per-core CPU time
+pub struct CpuCollector {
+    /// Total CPU seconds
+    cpu_seconds_total: CounterVec,
+}
+
+impl CpuCollector {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let cpu_usage_opts = Opts::new("cpu_seconds_total", "Total CPU seconds")
+            .namespace("simon")
+            .subsystem("cpu");
+        let cpu_seconds_total = CounterVec::new(cpu_usage_opts, &["core", "mode"])?;
+
+        Ok(CpuCollector { cpu_seconds_total })
+    }
+
+    fn update_cpu_usage(&self, label: &str, cpu: &Cpu) {
+        self.cpu_seconds_total
+            .with_label_values(&[label, "user"])
+            .inc_by(cpu.user() as f64);
+        self.cpu_seconds_total
+            .with_label_values(&[label, "system"])
+            .inc_by(cpu.system() as f64);
+        self.cpu_seconds_total
+            .with_label_values(&[label, "nice"])
+            .inc_by(cpu.nice() as f64);
+        self.cpu_seconds_total
+            .with_label_values(&[label, "idle"])
+            .inc_by(cpu.idle() as f64);
+    }
+}
+
+impl Collector for CpuCollector {
+    fn name(&self) -> &'static str {
+        "cpu"
+    }
+
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+        vec![Box::new(self.cpu_seconds_total.clone())]
+    }
+
+    fn refresh_kind(&self) -> RefreshKind {
+        RefreshKind::nothing().with_cpu(CpuRefreshKind::nothing().with_cpu_usage())
+    }
+
+    fn collect(&mut self, system: &System) -> Result<(), CollectError> {
+        self.cpu_seconds_total.reset();
+        // Update CPU usage per core
+        for (i, cpu) in system.cpus().iter().enumerate() {
+            self.update_cpu_usage(&i.to_string(), cpu);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,504 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prometheus::{CounterVec, GaugeVec, Opts};
+use serde::Deserialize;
+use sysinfo::System;
+
+use super::{CollectError, Collector};
+
+pub const DHCP_LEASES_PATH: &str = "/tmp/dhcp.leases";
+const DHCP_CONFIG_PATH: &str = "/etc/config/dhcp";
+const NETWORK_CONFIG_PATH: &str = "/etc/config/network";
+
+/// OpenWrt defaults for a dhcp section without `start` or `limit`
+const DEFAULT_POOL_START: u32 = 100;
+const DEFAULT_POOL_LIMIT: u32 = 150;
+
+/// A single lease, as listed in the dnsmasq lease file
+#[derive(Debug, Clone)]
+pub struct Lease {
+    /// Unix timestamp at which the lease expires, 0 for infinite leases
+    pub expiry: u64,
+    /// Client hardware address, lower case
+    pub mac: String,
+    /// Leased address
+    pub ip: IpAddr,
+    /// Hostname sent by the client, if any
+    pub hostname: Option<String>,
+}
+
+impl Lease {
+    /// Whether the lease ran out at `now`. dnsmasq drops expired leases from the file
+    /// only when it next writes it.
+    fn is_expired(&self, now: u64) -> bool {
+        self.expiry != 0 && self.expiry <= now
+    }
+}
+
+/// Parses one line of the dnsmasq lease file.
+///
+/// Lines look like `1700000000 aa:bb:cc:dd:ee:ff 192.168.1.10 laptop 01:aa:bb:cc:dd:ee:ff`,
+/// with `*` standing in for an unknown hostname or client id. DHCPv6 leases use the
+/// same layout with the IAID in place of the MAC address.
+pub fn parse_lease(line: &str) -> Option<Lease> {
+    let mut fields = line.split_whitespace();
+    let expiry = fields.next()?.parse().ok()?;
+    let mac = fields.next()?.to_ascii_lowercase();
+    let ip = fields.next()?.parse().ok()?;
+    let hostname = fields
+        .next()
+        .filter(|name| *name != "*")
+        .map(str::to_string);
+
+    Some(Lease {
+        expiry,
+        mac,
+        ip,
+        hostname,
+    })
+}
+
+/// Reads all leases from the dnsmasq lease file, or none when the file does not exist
+pub fn read_leases(path: &Path) -> io::Result<Vec<Lease>> {
+    match fs::read_to_string(path) {
+        Ok(content) => Ok(content.lines().filter_map(parse_lease).collect()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A `config` section of a UCI file
+#[derive(Debug, Default)]
+pub struct UciSection {
+    /// Section type, e.g. `dhcp` or `interface`
+    pub kind: String,
+    /// Section name, empty for anonymous sections
+    pub name: String,
+    /// Values of `option` and `list` lines, by option name
+    pub options: HashMap<String, Vec<String>>,
+}
+
+impl UciSection {
+    fn option(&self, name: &str) -> Option<&str> {
+        self.options.get(name)?.first().map(String::as_str)
+    }
+}
+
+/// Parses a UCI configuration file such as `/etc/config/dhcp`.
+///
+/// Sections look like
+/// ```text
+/// config dhcp 'lan'
+///     option interface 'lan'
+///     option start '100'
+/// ```
+/// Values may be single quoted, double quoted or bare.
+pub fn parse_uci(content: &str) -> Vec<UciSection> {
+    let mut sections: Vec<UciSection> = Vec::new();
+
+    for line in content.lines() {
+        let line = line.trim();
+        let Some((keyword, rest)) = line.split_once(char::is_whitespace) else {
+            continue;
+        };
+        let rest = rest.trim();
+        match keyword {
+            "config" => {
+                let (kind, name) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                sections.push(UciSection {
+                    kind: unquote(kind).to_string(),
+                    name: unquote(name.trim()).to_string(),
+                    options: HashMap::new(),
+                });
+            }
+            "option" | "list" => {
+                let Some(section) = sections.last_mut() else {
+                    continue;
+                };
+                let Some((name, value)) = rest.split_once(char::is_whitespace) else {
+                    continue;
+                };
+                section
+                    .options
+                    .entry(unquote(name).to_string())
+                    .or_default()
+                    .push(unquote(value.trim()).to_string());
+            }
+            _ => {}
+        }
+    }
+
+    sections
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('\'')
+        .and_then(|v| v.strip_suffix('\''))
+        .or_else(|| value.strip_prefix('"').and_then(|v| v.strip_suffix('"')))
+        .unwrap_or(value)
+}
+
+/// The dynamic IPv4 address range served on an interface
+#[derive(Debug)]
+pub struct Pool {
+    /// Logical interface name, e.g. `lan`
+    pub interface: String,
+    /// First address of the pool
+    pub first: Ipv4Addr,
+    /// Number of addresses in the pool
+    pub size: u32,
+}
+
+impl Pool {
+    fn contains(&self, ip: &IpAddr) -> bool {
+        let IpAddr::V4(ip) = ip else {
+            return false;
+        };
+        let offset = u32::from(*ip).wrapping_sub(u32::from(self.first));
+        offset < self.size
+    }
+}
+
+/// Works out the DHCP pools from the dhcp and network UCI configuration.
+///
+/// The pool starts `start` addresses into the interface subnet and holds `limit`
+/// addresses. Interfaces with `ignore` set or DHCPv4 disabled have no pool.
+pub fn parse_pools(dhcp_config: &str, network_config: &str) -> Vec<Pool> {
+    let networks: HashMap<String, (Ipv4Addr, Ipv4Addr)> = parse_uci(network_config)
+        .into_iter()
+        .filter(|section| section.kind == "interface")
+        .filter_map(|section| {
+            let ipaddr = section.option("ipaddr")?;
+            let (address, netmask) = match ipaddr.split_once('/') {
+                Some((address, prefix)) => {
+                    let prefix: u32 = prefix.parse().ok()?;
+                    let netmask = u32::MAX
+                        .checked_shl(32u32.checked_sub(prefix)?)
+                        .unwrap_or(0);
+                    (address.parse().ok()?, Ipv4Addr::from(netmask))
+                }
+                None => (
+                    ipaddr.parse().ok()?,
+                    section.option("netmask")?.parse().ok()?,
+                ),
+            };
+            Some((section.name, (address, netmask)))
+        })
+        .collect();
+
+    parse_uci(dhcp_config)
+        .into_iter()
+        .filter(|section| section.kind == "dhcp")
+        .filter(|section| section.option("ignore") != Some("1"))
+        .filter(|section| section.option("dhcpv4") != Some("disabled"))
+        .filter_map(|section| {
+            let interface = section.option("interface")?.to_string();
+            let (address, netmask) = networks.get(&interface)?;
+            let start = section
+                .option("start")
+                .and_then(|start| start.parse().ok())
+                .unwrap_or(DEFAULT_POOL_START);
+            let size = section
+                .option("limit")
+                .and_then(|limit| limit.parse().ok())
+                .unwrap_or(DEFAULT_POOL_LIMIT);
+            let network = u32::from(*address) & u32::from(*netmask);
+            Some(Pool {
+                interface,
+                first: Ipv4Addr::from(network.wrapping_add(start)),
+                size,
+            })
+        })
+        .collect()
+}
+
+fn read_pools(dhcp_config: &Path, network_config: &Path) -> io::Result<Vec<Pool>> {
+    let dhcp_config = match fs::read_to_string(dhcp_config) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let network_config = fs::read_to_string(network_config)?;
+    Ok(parse_pools(&dhcp_config, &network_config))
+}
+
+/// Collector for the DHCP pool and lease metrics
+pub struct DhcpCollector {
+    /// Number of addresses in the DHCP pool, per interface
+    pool_size: GaugeVec,
+    /// Number of leases handed out from the DHCP pool, per interface
+    pool_leases: GaugeVec,
+    /// Time at which a lease expires, as a unix timestamp
+    lease_expiry: GaugeVec,
+    /// Number of new leases granted, per interface
+    leases_granted_total: CounterVec,
+
+    /// Leases seen in the previous cycle, `None` before the first cycle
+    previous_leases: Option<HashSet<(String, IpAddr)>>,
+    options: DhcpOptions,
+}
+
+/// Options of the DHCP collector
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DhcpOptions {
+    /// dnsmasq lease file
+    leases_file: PathBuf,
+    /// UCI file with the dhcp sections
+    dhcp_config: PathBuf,
+    /// UCI file with the interface sections
+    network_config: PathBuf,
+}
+
+impl Default for DhcpOptions {
+    fn default() -> Self {
+        DhcpOptions {
+            leases_file: PathBuf::from(DHCP_LEASES_PATH),
+            dhcp_config: PathBuf::from(DHCP_CONFIG_PATH),
+            network_config: PathBuf::from(NETWORK_CONFIG_PATH),
+        }
+    }
+}
+
+impl DhcpCollector {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let pool_size_opts = Opts::new(
+            "pool_size",
+            "Number of addresses in the DHCP pool, per interface",
+        )
+        .namespace("simon")
+        .subsystem("dhcp");
+        let pool_size = GaugeVec::new(pool_size_opts, &["interface"])?;
+
+        let pool_leases_opts = Opts::new(
+            "pool_leases",
+            "Number of leases handed out from the DHCP pool, per interface",
+        )
+        .namespace("simon")
+        .subsystem("dhcp");
+        let pool_leases = GaugeVec::new(pool_leases_opts, &["interface"])?;
+
+        let lease_expiry_opts = Opts::new(
+            "lease_expiry_timestamp_seconds",
+            "Time at which a lease expires, as a unix timestamp",
+        )
+        .namespace("simon")
+        .subsystem("dhcp");
+        let lease_expiry = GaugeVec::new(lease_expiry_opts, &["mac", "ip", "hostname"])?;
+
+        let leases_granted_total_opts = Opts::new(
+            "leases_granted_total",
+            "Number of new leases granted, per interface",
+        )
+        .namespace("simon")
+        .subsystem("dhcp");
+        let leases_granted_total = CounterVec::new(leases_granted_total_opts, &["interface"])?;
+
+        Ok(DhcpCollector {
+            pool_size,
+            pool_leases,
+            lease_expiry,
+            leases_granted_total,
+            previous_leases: None,
+            options: DhcpOptions::default(),
+        })
+    }
+
+    /// Reads the DHCP configuration and lease file and updates the metrics
+    fn update_dhcp_metrics(&mut self) -> io::Result<()> {
+        let pools = read_pools(&self.options.dhcp_config, &self.options.network_config)?;
+        let leases = read_leases(&self.options.leases_file)?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        self.update_leases(&pools, &leases, now);
+        Ok(())
+    }
+
+    fn update_leases(&mut self, pools: &[Pool], leases: &[Lease], now: u64) {
+        let mut in_use: HashMap<&str, u64> = HashMap::new();
+        let mut granted: HashMap<&str, u64> = HashMap::new();
+        let mut current = HashSet::new();
+
+        // Leases come and go, so rebuild the gauges every cycle
+        self.lease_expiry.reset();
+        for lease in leases.iter().filter(|lease| !lease.is_expired(now)) {
+            self.lease_expiry
+                .with_label_values(&[
+                    lease.mac.as_str(),
+                    lease.ip.to_string().as_str(),
+                    lease.hostname.as_deref().unwrap_or_default(),
+                ])
+                .set(lease.expiry as f64);
+
+            // Leases present at startup were not granted while we were watching
+            let key = (lease.mac.clone(), lease.ip);
+            let is_new = self
+                .previous_leases
+                .as_ref()
+                .is_some_and(|previous| !previous.contains(&key));
+            current.insert(key);
+
+            let Some(pool) = pools.iter().find(|pool| pool.contains(&lease.ip)) else {
+                continue;
+            };
+            *in_use.entry(pool.interface.as_str()).or_default() += 1;
+            if is_new {
+                *granted.entry(pool.interface.as_str()).or_default() += 1;
+            }
+        }
+
+        self.pool_size.reset();
+        self.pool_leases.reset();
+        for pool in pools {
+            let interface = pool.interface.as_str();
+            self.pool_size
+                .with_label_values(&[interface])
+                .set(pool.size as f64);
+            self.pool_leases
+                .with_label_values(&[interface])
+                .set(in_use.get(interface).copied().unwrap_or_default() as f64);
+            self.leases_granted_total
+                .with_label_values(&[interface])
+                .inc_by(granted.get(interface).copied().unwrap_or_default() as f64);
+        }
+
+        self.previous_leases = Some(current);
+    }
+}
+
+impl Collector for DhcpCollector {
+    fn name(&self) -> &'static str {
+        "dhcp"
+    }
+
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+        vec![
+            Box::new(self.pool_size.clone()),
+            Box::new(self.pool_leases.clone()),
+            Box::new(self.lease_expiry.clone()),
+            Box::new(self.leases_granted_total.clone()),
+        ]
+    }
+
+    fn configure(&mut self, options: &toml::Table) -> Result<(), Box<dyn std::error::Error>> {
+        self.options = options.clone().try_into()?;
+        Ok(())
+    }
+
+    fn collect(&mut self, _system: &System) -> Result<(), CollectError> {
+        Ok(self.update_dhcp_metrics()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DHCP_CONFIG: &str = include_str!("../../fixtures/dhcp/dhcp");
+    const NETWORK_CONFIG: &str = include_str!("../../fixtures/dhcp/network");
+    const LEASES: &str = include_str!("../../fixtures/dhcp/dhcp.leases");
+
+    /// Between the expiry of the old lease and that of the current ones
+    const NOW: u64 = 1_700_000_000;
+
+    fn leases() -> Vec<Lease> {
+        LEASES.lines().filter_map(parse_lease).collect()
+    }
+
+    #[test]
+    fn parses_quoted_and_bare_uci_values() {
+        let sections = parse_uci(DHCP_CONFIG);
+        assert_eq!(sections.len(), 5);
+
+        assert_eq!(sections[0].kind, "dnsmasq");
+        assert_eq!(sections[0].name, "");
+        assert_eq!(sections[0].option("local"), Some("/lan/"));
+        assert_eq!(
+            sections[0].options["server"],
+            ["/example.com/10.0.0.53".to_string()]
+        );
+
+        assert_eq!(sections[1].name, "lan");
+        assert_eq!(sections[1].option("limit"), Some("50"));
+        // Double quoted names and values, bare values
+        assert_eq!(sections[2].name, "guest");
+        assert_eq!(sections[2].option("interface"), Some("guest"));
+        assert_eq!(sections[2].option("start"), Some("10"));
+        assert_eq!(sections[2].option("limit"), None);
+    }
+
+    #[test]
+    fn works_out_pools() {
+        let pools = parse_pools(DHCP_CONFIG, NETWORK_CONFIG);
+        let pools: Vec<(&str, Ipv4Addr, u32)> = pools
+            .iter()
+            .map(|pool| (pool.interface.as_str(), pool.first, pool.size))
+            .collect();
+        // wan is ignored and iot has DHCPv4 disabled. guest has no limit and a
+        // prefix length instead of a netmask.
+        assert_eq!(
+            pools,
+            [
+                ("lan", Ipv4Addr::new(192, 168, 1, 100), 50),
+                ("guest", Ipv4Addr::new(10, 10, 0, 10), DEFAULT_POOL_LIMIT),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_leases() {
+        let leases = leases();
+        // The duid line isn't a lease
+        assert_eq!(leases.len(), 7);
+        assert_eq!(leases[0].hostname.as_deref(), Some("laptop"));
+        assert_eq!(leases[1].mac, "aa:bb:cc:dd:ee:02");
+        assert_eq!(leases[1].hostname, None);
+        assert_eq!(leases[3].expiry, 0);
+        assert_eq!(leases[6].ip, "fd00::1a2b".parse::<IpAddr>().unwrap());
+
+        assert!(leases[2].is_expired(NOW));
+        assert!(!leases[0].is_expired(NOW));
+        assert!(!leases[3].is_expired(NOW));
+    }
+
+    #[test]
+    fn counts_leases_per_pool() {
+        let mut collector = DhcpCollector::new().unwrap();
+        let pools = parse_pools(DHCP_CONFIG, NETWORK_CONFIG);
+        collector.update_leases(&pools, &leases(), NOW);
+
+        // .120 expired, .10 and .150 lie outside the pool
+        let pool_leases = collector.pool_leases.clone();
+        let in_use = |interface| pool_leases.with_label_values(&[interface]).get();
+        assert_eq!(in_use("lan"), 2.0);
+        assert_eq!(in_use("guest"), 1.0);
+        assert_eq!(collector.pool_size.with_label_values(&["lan"]).get(), 50.0);
+
+        let expired = ["aa:bb:cc:dd:ee:03", "192.168.1.120", "old-phone"];
+        assert!(collector
+            .lease_expiry
+            .remove_label_values(&expired)
+            .is_err());
+
+        // Leases present on the first cycle weren't granted while watching
+        let leases_granted_total = collector.leases_granted_total.clone();
+        let granted = |interface| leases_granted_total.with_label_values(&[interface]).get();
+        assert_eq!(granted("lan"), 0.0);
+
+        let mut leases = leases();
+        leases.extend(parse_lease(
+            "1800000000 aa:bb:cc:dd:ee:07 192.168.1.130 new *",
+        ));
+        collector.update_leases(&pools, &leases, NOW);
+        assert_eq!(granted("lan"), 1.0);
+        assert_eq!(in_use("lan"), 3.0);
+    }
+}
@@ -0,0 +1,459 @@
+use std::collections::HashMap;
+use std::io;
+use std::process::Command;
+
+use prometheus::{CounterVec, Opts};
+use serde::Deserialize;
+use sysinfo::System;
+use tracing::debug;
+
+use super::{CollectError, Collector};
+use serde_json::Value;
+
+/// Packet and byte counters of a firewall rule
+#[derive(Debug)]
+pub struct RuleCounter {
+    /// Address family (ip, ip6, inet, ...)
+    pub family: String,
+    /// Table the rule belongs to
+    pub table: String,
+    /// Chain the rule belongs to
+    pub chain: String,
+    /// Rule comment
+    pub rule: String,
+    /// Packets matched by the rule
+    pub packets: u64,
+    /// Bytes matched by the rule
+    pub bytes: u64,
+}
+
+/// Parses the output of `nft -j list ruleset`.
+///
+/// Only rules with a comment and an anonymous `counter` statement are returned. Rule
+/// handles change whenever the ruleset is reloaded, so rules without a comment have
+/// no stable label and are left out.
+pub fn parse_nft_json(output: &str) -> Result<Vec<RuleCounter>, serde_json::Error> {
+    let ruleset: Value = serde_json::from_str(output)?;
+    let objects = ruleset["nftables"].as_array().cloned().unwrap_or_default();
+
+    let counters = objects
+        .iter()
+        .filter_map(|object| {
+            let rule = object.get("rule")?;
+            let counter = rule["expr"]
+                .as_array()?
+                .iter()
+                .find_map(|expr| expr.get("counter").filter(|c| c.is_object()))?;
+            Some(RuleCounter {
+                family: rule["family"].as_str()?.to_string(),
+                table: rule["table"].as_str()?.to_string(),
+                chain: rule["chain"].as_str()?.to_string(),
+                rule: rule["comment"].as_str()?.to_string(),
+                packets: counter["packets"].as_u64()?,
+                bytes: counter["bytes"].as_u64()?,
+            })
+        })
+        .collect();
+
+    Ok(counters)
+}
+
+/// Parses the output of `iptables-save -c` (or `ip6tables-save -c`).
+///
+/// Rule lines look like
+/// `[120:9600] -A INPUT -i lo -m comment --comment "allow loopback" -j ACCEPT`.
+/// As with nftables, rules without a comment are left out: their position in the
+/// chain changes as rules are added and removed.
+pub fn parse_iptables_save(output: &str, family: &str) -> Vec<RuleCounter> {
+    let mut table = "";
+    let mut counters = Vec::new();
+
+    for line in output.lines() {
+        if let Some(name) = line.strip_prefix('*') {
+            table = name.trim();
+            continue;
+        }
+        let Some(rest) = line.strip_prefix('[') else {
+            continue;
+        };
+        let Some((packets_bytes, rule)) = rest.split_once(']') else {
+            continue;
+        };
+        let Some((packets, bytes)) = packets_bytes.split_once(':') else {
+            continue;
+        };
+        let mut args = rule.split_whitespace();
+        if args.next() != Some("-A") {
+            continue;
+        }
+        let Some(chain) = args.next() else {
+            continue;
+        };
+        let Some(label) = parse_comment(rule) else {
+            continue;
+        };
+
+        let (Ok(packets), Ok(bytes)) = (packets.parse(), bytes.parse()) else {
+            continue;
+        };
+        counters.push(RuleCounter {
+            family: family.to_string(),
+            table: table.to_string(),
+            chain: chain.to_string(),
+            rule: label,
+            packets,
+            bytes,
+        });
+    }
+
+    counters
+}
+
+/// Extracts the argument of `--comment`, which is quoted when it contains spaces
+fn parse_comment(rule: &str) -> Option<String> {
+    let (_, rest) = rule.split_once("--comment ")?;
+    let comment = match rest.strip_prefix('"') {
+        Some(quoted) => {
+            let mut comment = String::new();
+            let mut chars = quoted.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => comment.extend(chars.next()),
+                    '"' => break,
+                    c => comment.push(c),
+                }
+            }
+            comment
+        }
+        None => rest.split_whitespace().next()?.to_string(),
+    };
+    Some(comment)
+}
+
+/// Runs a command, returning `None` when it is not installed
+fn run(program: &str, args: &[&str]) -> io::Result<Option<String>> {
+    let output = match Command::new(program).args(args).output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(Some(String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+/// Where the rule counters are read from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Backend {
+    /// nftables when available, iptables otherwise
+    #[default]
+    Auto,
+    Nftables,
+    Iptables,
+}
+
+/// Options of the firewall collector
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FirewallOptions {
+    backend: Backend,
+}
+
+/// Reads rule counters from nftables, falling back to iptables on older firewalls.
+///
+/// In auto mode nft failing counts as nftables not being available, as on hosts
+/// running iptables-legacy.
+fn read_rule_counters(backend: Backend) -> io::Result<Vec<RuleCounter>> {
+    if backend != Backend::Iptables {
+        match run("nft", &["-j", "list", "ruleset"]) {
+            Ok(Some(output)) => {
+                return parse_nft_json(&output)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            Ok(None) if backend == Backend::Nftables => return Ok(Vec::new()),
+            Err(e) if backend == Backend::Nftables => return Err(e),
+            Ok(None) => {}
+            Err(e) => debug!("Falling back to iptables: {}", e),
+        }
+    }
+
+    let mut counters = Vec::new();
+    if let Some(output) = run("iptables-save", &["-c"])? {
+        counters.extend(parse_iptables_save(&output, "ip"));
+    }
+    if let Some(output) = run("ip6tables-save", &["-c"])? {
+        counters.extend(parse_iptables_save(&output, "ip6"));
+    }
+    Ok(counters)
+}
+
+/// Collector for the firewall rule metrics
+pub struct FirewallCollector {
+    /// Total number of packets matched, per firewall rule
+    rule_packets_total: CounterVec,
+    /// Total number of bytes matched, per firewall rule
+    rule_bytes_total: CounterVec,
+
+    /// Counter values reported by the previous cycle, per rule
+    previous_counters: HashMap<[String; 4], (u64, u64)>,
+    options: FirewallOptions,
+}
+
+impl FirewallCollector {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let rule_packets_total_opts = Opts::new(
+            "rule_packets_total",
+            "Total number of packets matched, per firewall rule",
+        )
+        .namespace("simon")
+        .subsystem("firewall");
+        let rule_packets_total = CounterVec::new(
+            rule_packets_total_opts,
+            &["family", "table", "chain", "rule"],
+        )?;
+
+        let rule_bytes_total_opts = Opts::new(
+            "rule_bytes_total",
+            "Total number of bytes matched, per firewall rule",
+        )
+        .namespace("simon")
+        .subsystem("firewall");
+        let rule_bytes_total =
+            CounterVec::new(rule_bytes_total_opts, &["family", "table", "chain", "rule"])?;
+
+        Ok(FirewallCollector {
+            rule_packets_total,
+            rule_bytes_total,
+            previous_counters: HashMap::new(),
+            options: FirewallOptions::default(),
+        })
+    }
+
+    /// Reads the rule counters from nftables or iptables and updates the metrics
+    fn update_firewall_metrics(&mut self) -> io::Result<()> {
+        let counters = read_rule_counters(self.options.backend)?;
+        self.update_counters(counters);
+        Ok(())
+    }
+
+    fn update_counters(&mut self, counters: Vec<RuleCounter>) {
+        // Several rules can share a comment, report them as one
+        let mut current: HashMap<[String; 4], (u64, u64)> = HashMap::new();
+        for counter in counters {
+            let key = [counter.family, counter.table, counter.chain, counter.rule];
+            let entry = current.entry(key).or_default();
+            entry.0 += counter.packets;
+            entry.1 += counter.bytes;
+        }
+
+        for (key, (packets, bytes)) in &current {
+            let labels = [
+                key[0].as_str(),
+                key[1].as_str(),
+                key[2].as_str(),
+                key[3].as_str(),
+            ];
+            // Rule counters are running totals which restart when the ruleset is reloaded
+            let (previous_packets, previous_bytes) =
+                self.previous_counters.get(key).copied().unwrap_or_default();
+            let packets_delta = if *packets >= previous_packets {
+                packets - previous_packets
+            } else {
+                *packets
+            };
+            let bytes_delta = if *bytes >= previous_bytes {
+                bytes - previous_bytes
+            } else {
+                *bytes
+            };
+
+            self.rule_packets_total
+                .with_label_values(&labels)
+                .inc_by(packets_delta as f64);
+            self.rule_bytes_total
+                .with_label_values(&labels)
+                .inc_by(bytes_delta as f64);
+        }
+
+        // Drop the series of rules that were removed
+        for key in self.previous_counters.keys() {
+            if !current.contains_key(key) {
+                let labels = [
+                    key[0].as_str(),
+                    key[1].as_str(),
+                    key[2].as_str(),
+                    key[3].as_str(),
+                ];
+                let _ = self.rule_packets_total.remove_label_values(&labels);
+                let _ = self.rule_bytes_total.remove_label_values(&labels);
+            }
+        }
+
+        self.previous_counters = current;
+    }
+}
+
+impl Collector for FirewallCollector {
+    fn name(&self) -> &'static str {
+        "firewall"
+    }
+
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+        vec![
+            Box::new(self.rule_packets_total.clone()),
+            Box::new(self.rule_bytes_total.clone()),
+        ]
+    }
+
+    fn configure(&mut self, options: &toml::Table) -> Result<(), Box<dyn std::error::Error>> {
+        self.options = options.clone().try_into()?;
+        Ok(())
+    }
+
+    fn collect(&mut self, _system: &System) -> Result<(), CollectError> {
+        Ok(self.update_firewall_metrics()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NFT_RULESET: &str = include_str!("../../fixtures/firewall/nft-ruleset.json");
+    const IPTABLES_SAVE: &str = include_str!("../../fixtures/firewall/iptables-save.txt");
+    const IP6TABLES_SAVE: &str = include_str!("../../fixtures/firewall/ip6tables-save.txt");
+
+    fn rules(counters: &[RuleCounter]) -> Vec<(&str, &str, &str, &str, u64, u64)> {
+        counters
+            .iter()
+            .map(|c| {
+                (
+                    c.family.as_str(),
+                    c.table.as_str(),
+                    c.chain.as_str(),
+                    c.rule.as_str(),
+                    c.packets,
+                    c.bytes,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_commented_nft_rules_with_counters() {
+        let counters = parse_nft_json(NFT_RULESET).unwrap();
+        assert_eq!(
+            rules(&counters),
+            [
+                (
+                    "inet",
+                    "fw4",
+                    "input",
+                    "!fw4: Accept traffic from loopback",
+                    1520,
+                    121600
+                ),
+                ("inet", "fw4", "input", "Allow \"SSH\" from LAN", 42, 2520),
+                ("ip6", "filter", "forward", "Allow ICMPv6", 7, 560),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_nft_output() {
+        assert!(parse_nft_json("not json").is_err());
+        assert!(parse_nft_json("{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn parses_commented_iptables_rules_with_counters() {
+        let counters = parse_iptables_save(IPTABLES_SAVE, "ip");
+        assert_eq!(
+            rules(&counters),
+            [
+                ("ip", "nat", "POSTROUTING", "masquerade", 310, 18600),
+                ("ip", "filter", "INPUT", "allow loopback", 120, 9600),
+                ("ip", "filter", "INPUT", "say \"hi\" to ssh", 0, 0),
+                ("ip", "filter", "FORWARD", "lan to wan", 55, 3300),
+                ("ip", "filter", "FORWARD", "lan to wan", 45, 2700),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_ip6tables_rules() {
+        let counters = parse_iptables_save(IP6TABLES_SAVE, "ip6");
+        assert_eq!(
+            rules(&counters),
+            [("ip6", "filter", "INPUT", "allow icmpv6", 64, 5120)]
+        );
+    }
+
+    #[test]
+    fn parses_comments() {
+        assert_eq!(
+            parse_comment(r#"-A INPUT -m comment --comment "allow loopback" -j ACCEPT"#).as_deref(),
+            Some("allow loopback")
+        );
+        assert_eq!(
+            parse_comment(r#"-A INPUT -m comment --comment "a \"quoted\" \\ word" -j ACCEPT"#)
+                .as_deref(),
+            Some(r#"a "quoted" \ word"#)
+        );
+        assert_eq!(
+            parse_comment("-A INPUT -m comment --comment masquerade -j ACCEPT").as_deref(),
+            Some("masquerade")
+        );
+        assert_eq!(parse_comment("-A INPUT -i lo -j ACCEPT"), None);
+    }
+
+    #[test]
+    fn sums_shared_comments_and_removes_deleted_rules() {
+        let mut collector = FirewallCollector::new().unwrap();
+        collector.update_counters(parse_iptables_save(IPTABLES_SAVE, "ip"));
+
+        let lan_to_wan = ["ip", "filter", "FORWARD", "lan to wan"];
+        assert_eq!(
+            collector
+                .rule_packets_total
+                .with_label_values(&lan_to_wan)
+                .get(),
+            100.0
+        );
+
+        // The ruleset was reloaded without the loopback rule, counters restart
+        let reloaded = IPTABLES_SAVE
+            .lines()
+            .filter(|line| !line.contains("allow loopback"))
+            .map(|line| {
+                line.replace("[55:3300]", "[5:300]")
+                    .replace("[45:2700]", "[0:0]")
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        collector.update_counters(parse_iptables_save(&reloaded, "ip"));
+
+        assert_eq!(
+            collector
+                .rule_packets_total
+                .with_label_values(&lan_to_wan)
+                .get(),
+            105.0
+        );
+        let loopback = ["ip", "filter", "INPUT", "allow loopback"];
+        assert!(collector
+            .rule_packets_total
+            .remove_label_values(&loopback)
+            .is_err());
+        assert!(collector
+            .rule_bytes_total
+            .remove_label_values(&loopback)
+            .is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use prometheus::{Gauge, Opts};
+use sysinfo::{MemoryRefreshKind, RefreshKind, System};
+
+use super::{CollectError, Collector};
+
+/// Collector for physical memory usage
+pub struct MemoryCollector {
+    /// Total physical memory in bytes
+    memory_total: Gauge,
+    /// Free physical memory in bytes
+    memory_free: Gauge,
+    /// Available physical memory in bytes
+    memory_available: Gauge,
+    /// Used physical memory in bytes
+    memory_used: Gauge,
+}
+
+impl MemoryCollector {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let memory_total_opts = Opts::new("total_bytes", "Total physical memory in bytes")
+            .namespace("simon")
+            .subsystem("memory");
+        let memory_total = Gauge::with_opts(memory_total_opts)?;
+
+        let memory_free_opts = Opts::new("free_bytes", "Free physical memory in bytes")
+            .namespace("simon")
+            .subsystem("memory");
+        let memory_free = Gauge::with_opts(memory_free_opts)?;
+
+        let memory_available_opts =
+            Opts::new("available_bytes", "Available physical memory in bytes")
+                .namespace("simon")
+                .subsystem("memory");
+        let memory_available = Gauge::with_opts(memory_available_opts)?;
+
+        let memory_used_opts = Opts::new("used_bytes", "Used physical memory in bytes")
+            .namespace("simon")
+            .subsystem("memory");
+        let memory_used = Gauge::with_opts(memory_used_opts)?;
+
+        Ok(MemoryCollector {
+            memory_total,
+            memory_free,
+            memory_available,
+            memory_used,
+        })
+    }
+}
+
+impl Collector for MemoryCollector {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+        vec![
+            Box::new(self.memory_total.clone()),
+            Box::new(self.memory_free.clone()),
+            Box::new(self.memory_available.clone()),
+            Box::new(self.memory_used.clone()),
+        ]
+    }
+
+    fn refresh_kind(&self) -> RefreshKind {
+        RefreshKind::nothing().with_memory(MemoryRefreshKind::nothing().with_ram())
+    }
+
+    fn collect(&mut self, system: &System) -> Result<(), CollectError> {
+        self.memory_total.set(system.total_memory() as f64);
+        self.memory_free.set(system.free_memory() as f64);
+        self.memory_available.set(system.available_memory() as f64);
+        self.memory_used.set(system.used_memory() as f64);
+        Ok(())
+    }
+}
@@ -0,0 +1,911 @@
+pub mod conntrack;
+pub mod cpu;
+pub mod dhcp;
+pub mod firewall;
+pub mod memory;
+pub mod network;
+pub mod presence;
+pub mod process;
+pub mod swap;
+pub mod wireguard;
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, Instant};
+
+use prometheus::proto::MetricFamily;
+use prometheus::Registry;
+use serde::de::DeserializeOwned;
+use sysinfo::{RefreshKind, System};
+use tokio::task::JoinSet;
+use tracing::{debug, error, warn};
+
+use crate::config::Config;
+use crate::exporter::ExporterMetrics;
+use crate::exposition::Filter;
+
+use conntrack::ConntrackCollector;
+use cpu::CpuCollector;
+use dhcp::DhcpCollector;
+use firewall::FirewallCollector;
+use memory::MemoryCollector;
+use network::NetworkCollector;
+use presence::PresenceCollector;
+use process::ProcessCollector;
+use swap::SwapCollector;
+use wireguard::WireguardCollector;
+
+/// Error returned by a collector that failed to refresh its metrics
+pub type CollectError = Box<dyn std::error::Error + Send + Sync>;
+
+/// A source of metrics, refreshed once per collection cycle
+pub trait Collector: Send {
+    /// Short, unique name of the collector, e.g. `cpu`
+    fn name(&self) -> &'static str;
+
+    /// The metric families exported by this collector
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>>;
+
+    /// Applies the collector specific options from the configuration file
+    fn configure(&mut self, options: &toml::Table) -> Result<(), Box<dyn std::error::Error>> {
+        no_options(self.name(), options)
+    }
+
+    /// The system information the collector reads, refreshed right before it runs.
+    /// Collectors that don't read from sysinfo need nothing.
+    fn refresh_kind(&self) -> RefreshKind {
+        RefreshKind::nothing()
+    }
+
+    /// Refreshes the metrics from the current state of the system
+    fn collect(&mut self, system: &System) -> Result<(), CollectError>;
+}
+
+/// Names of every collector simon knows about, in collection order
+pub const NAMES: &[&str] = &[
+    "cpu",
+    "memory",
+    "swap",
+    "process",
+    "network",
+    "conntrack",
+    "presence",
+    "wireguard",
+    "firewall",
+    "dhcp",
+];
+
+/// Subsystems grouping several collectors, each served on `/metrics/<subsystem>`.
+///
+/// Only `system` groups collectors, every other endpoint such as
+/// `/metrics/process` or `/metrics/network` is that of a single collector.
+pub const SUBSYSTEMS: &[(&str, &[&str])] = &[("system", &["cpu", "memory", "swap"])];
+
+/// Names of the collectors making up a subsystem or, failing that, the collector of
+/// that name
+pub fn subsystem(name: &str) -> Option<&'static [&'static str]> {
+    if let Some((_, collectors)) = SUBSYSTEMS.iter().find(|(subsystem, _)| *subsystem == name) {
+        return Some(collectors);
+    }
+    NAMES
+        .iter()
+        .position(|collector| *collector == name)
+        .map(|i| &NAMES[i..=i])
+}
+
+/// Creates the collector with the given name
+pub fn build(name: &str) -> Result<Box<dyn Collector>, Box<dyn std::error::Error>> {
+    let collector: Box<dyn Collector> = match name {
+        "cpu" => Box::new(CpuCollector::new()?),
+        "memory" => Box::new(MemoryCollector::new()?),
+        "swap" => Box::new(SwapCollector::new()?),
+        "process" => Box::new(ProcessCollector::new()?),
+        "network" => Box::new(NetworkCollector::new()?),
+        "conntrack" => Box::new(ConntrackCollector::new()?),
+        "presence" => Box::new(PresenceCollector::new()?),
+        "wireguard" => Box::new(WireguardCollector::new()?),
+        "firewall" => Box::new(FirewallCollector::new()?),
+        "dhcp" => Box::new(DhcpCollector::new()?),
+        _ => return Err(format!("Unknown collector: {}", name).into()),
+    };
+    Ok(collector)
+}
+
+/// Checks the options of the named collector as its `configure` does, without
+/// creating the collector
+pub fn validate_options(
+    name: &str,
+    options: &toml::Table,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match name {
+        "process" => parse_options::<process::ProcessOptions>(options),
+        "network" => parse_options::<network::NetworkOptions>(options),
+        "presence" => parse_options::<presence::PresenceOptions>(options),
+        "firewall" => parse_options::<firewall::FirewallOptions>(options),
+        "dhcp" => parse_options::<dhcp::DhcpOptions>(options),
+        _ if NAMES.contains(&name) => no_options(name, options),
+        _ => Err(format!("Unknown collector: {}", name).into()),
+    }
+}
+
+fn parse_options<T: DeserializeOwned>(
+    options: &toml::Table,
+) -> Result<(), Box<dyn std::error::Error>> {
+    options.clone().try_into::<T>()?;
+    Ok(())
+}
+
+/// Rejects any option, for collectors that have none
+fn no_options(name: &str, options: &toml::Table) -> Result<(), Box<dyn std::error::Error>> {
+    match options.keys().next() {
+        Some(key) => Err(format!("Unknown option for {} collector: {}", name, key).into()),
+        None => Ok(()),
+    }
+}
+
+/// Largest backoff of a failing collector, as a power of two of its interval
+const MAX_BACKOFF_EXPONENT: u32 = 4;
+
+/// How often a reconfiguration checks whether the runs in progress have ended
+const RUN_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// How a run of a collector ended
+pub enum Outcome {
+    Success,
+    /// The collector returned an error
+    Failed(CollectError),
+    /// The collector panicked
+    Panicked,
+    /// The collector didn't finish within its timeout
+    TimedOut,
+    /// The collector was still busy with a run that timed out earlier
+    Busy,
+}
+
+/// How a run of a collector ended, handed back to [`Collectors::finish`]
+pub struct Completion {
+    /// Position of the collector in `Collectors::entries`
+    index: usize,
+    outcome: Outcome,
+    duration: Duration,
+    /// Whether the run was scheduled, and the next one must be
+    on_schedule: bool,
+}
+
+/// A collector together with the system information it reads
+struct Instance {
+    collector: Box<dyn Collector>,
+    system: System,
+}
+
+impl Instance {
+    /// Refreshes only the system information the collector reads, then runs it
+    fn run(&mut self) -> Result<(), CollectError> {
+        self.system.refresh_specifics(self.collector.refresh_kind());
+        self.collector.collect(&self.system)
+    }
+}
+
+/// Locks an instance without waiting, `None` while a timed out run still holds it.
+/// Panics are caught inside the lock, so a poisoned lock is safe to recover.
+fn try_lock(instance: &Mutex<Instance>) -> Option<MutexGuard<'_, Instance>> {
+    match instance.try_lock() {
+        Ok(guard) => Some(guard),
+        Err(TryLockError::Poisoned(e)) => Some(e.into_inner()),
+        Err(TryLockError::WouldBlock) => None,
+    }
+}
+
+/// Locks every instance without waiting, or none of them. Returns the index of the
+/// first busy instance otherwise.
+fn try_lock_all(
+    instances: &[Arc<Mutex<Instance>>],
+) -> Result<Vec<MutexGuard<'_, Instance>>, usize> {
+    instances
+        .iter()
+        .enumerate()
+        .map(|(i, instance)| try_lock(instance).ok_or(i))
+        .collect()
+}
+
+/// Registers or unregisters the metrics of a collector, all of them or, on error,
+/// none
+fn register(
+    registry: &Registry,
+    collector: &dyn Collector,
+    enabled: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let apply = |metric, enabled| {
+        if enabled {
+            registry.register(metric)
+        } else {
+            registry.unregister(metric)
+        }
+    };
+    for (done, metric) in collector.describe().into_iter().enumerate() {
+        if let Err(e) = apply(metric, enabled) {
+            for metric in collector.describe().into_iter().take(done) {
+                let _ = apply(metric, !enabled);
+            }
+            return Err(e.into());
+        }
+    }
+    Ok(())
+}
+
+struct Entry {
+    name: &'static str,
+    /// Holds the metrics of this collector only, while it is enabled
+    registry: Registry,
+    /// Shared with the blocking thread running the collector
+    instance: Arc<Mutex<Instance>>,
+    enabled: bool,
+    /// Time between two runs
+    interval: Duration,
+    /// Time after which a run is abandoned
+    timeout: Duration,
+    /// Whether a run was started and hasn't been finished yet
+    running: bool,
+    /// When the last run was started
+    started: Instant,
+    /// Metrics gathered after the last run that finished, served while the
+    /// collector runs again
+    families: Vec<MetricFamily>,
+    /// When the collector is scheduled to run, before jitter
+    scheduled: Instant,
+    /// When the collector actually runs next, `scheduled` plus jitter
+    due: Instant,
+    /// Number of runs in a row that failed
+    failures: u32,
+    /// When the last run ended, successful or not
+    last_run: Option<Instant>,
+    /// When the last successful run ended
+    last_success: Option<Instant>,
+    /// While backing off after repeated failures, when the collector may run again
+    retry_at: Option<Instant>,
+}
+
+impl Entry {
+    /// A disabled collector, first due at `now` plus jitter
+    fn new(
+        name: &'static str,
+        collector: Box<dyn Collector>,
+        interval: Duration,
+        timeout: Duration,
+        now: Instant,
+    ) -> Entry {
+        Entry {
+            name,
+            registry: Registry::new(),
+            instance: Arc::new(Mutex::new(Instance {
+                collector,
+                // Collectors refresh only what they read, nothing is loaded up front
+                system: System::new(),
+            })),
+            enabled: false,
+            interval,
+            timeout,
+            running: false,
+            started: now,
+            families: Vec::new(),
+            scheduled: now,
+            due: now + jitter(interval),
+            failures: 0,
+            last_run: None,
+            last_success: None,
+            retry_at: None,
+        }
+    }
+
+    /// Whether the collector should run at `now`, on schedule or regardless of it
+    fn is_runnable(&self, now: Instant, on_schedule: bool) -> bool {
+        self.enabled
+            && !self.running
+            && self.retry_at.is_none_or(|retry_at| retry_at <= now)
+            && (!on_schedule || self.due <= now)
+    }
+
+    /// Runs the collector on a blocking thread, containing any panic, and adds the
+    /// run to `runs`. The run ends with the collector or with its timeout, whichever
+    /// comes first.
+    fn spawn(
+        &mut self,
+        index: usize,
+        now: Instant,
+        on_schedule: bool,
+        runs: &mut JoinSet<Completion>,
+    ) {
+        self.running = true;
+        self.started = now;
+        let instance = Arc::clone(&self.instance);
+        let timeout = self.timeout;
+        runs.spawn(async move {
+            let run = tokio::task::spawn_blocking(move || {
+                let start = Instant::now();
+                let Some(mut instance) = try_lock(&instance) else {
+                    return (Outcome::Busy, start.elapsed());
+                };
+                let outcome = match panic::catch_unwind(AssertUnwindSafe(|| instance.run())) {
+                    Ok(Ok(())) => Outcome::Success,
+                    Ok(Err(e)) => Outcome::Failed(e),
+                    Err(_) => Outcome::Panicked,
+                };
+                (outcome, start.elapsed())
+            });
+            let (outcome, duration) =
+                match tokio::time::timeout_at((now + timeout).into(), run).await {
+                    Ok(Ok(result)) => result,
+                    Ok(Err(_)) => (Outcome::Panicked, now.elapsed()),
+                    Err(_) => (Outcome::TimedOut, timeout),
+                };
+            Completion {
+                index,
+                outcome,
+                duration,
+                on_schedule,
+            }
+        });
+    }
+
+    /// Gathers the metrics of the collector, which must not be running
+    fn update_families(&mut self) {
+        self.families = if self.enabled {
+            self.registry.gather()
+        } else {
+            Vec::new()
+        };
+    }
+
+    /// Records how a run ended, backing off when the collector fails repeatedly
+    fn record(&mut self, outcome: &Outcome, now: Instant) {
+        match outcome {
+            Outcome::Success => {
+                self.failures = 0;
+                self.last_success = Some(now);
+                self.retry_at = None;
+                return;
+            }
+            Outcome::Failed(e) => warn!("Failed to update {} metrics: {}", self.name, e),
+            Outcome::Panicked => error!("The {} collector panicked", self.name),
+            Outcome::TimedOut => warn!(
+                "The {} collector didn't finish within {:?}",
+                self.name, self.timeout
+            ),
+            Outcome::Busy => warn!("The {} collector is still running", self.name),
+        }
+
+        self.failures += 1;
+        // A single failure is retried on schedule, after that the wait doubles
+        if self.failures > 1 {
+            let exponent = (self.failures - 1).min(MAX_BACKOFF_EXPONENT);
+            let backoff = self.interval * 2u32.pow(exponent);
+            debug!("Backing off the {} collector for {:?}", self.name, backoff);
+            self.retry_at = Some(now + backoff);
+        }
+    }
+
+    /// When the collector runs next, including any backoff
+    fn next_run(&self) -> Instant {
+        self.retry_at
+            .map_or(self.due, |retry_at| retry_at.max(self.due))
+    }
+
+    /// Schedules the next run one interval after the previous one, skipping runs
+    /// that were missed because collection fell behind
+    fn reschedule(&mut self, now: Instant) {
+        self.scheduled += self.interval;
+        if self.scheduled < now {
+            self.scheduled = now;
+        }
+        self.due = self.scheduled + jitter(self.interval);
+    }
+}
+
+/// Random delay of up to a tenth of `interval`, so collectors sharing an interval
+/// don't all run at the same instant
+fn jitter(interval: Duration) -> Duration {
+    let random = RandomState::new().hash_one(Instant::now());
+    let max = interval.as_millis() as u64 / 10;
+    Duration::from_millis(random.checked_rem(max).unwrap_or(0))
+}
+
+/// The set of collectors, each enabled collector's metrics registered with a registry
+/// of its own
+pub struct Collectors {
+    entries: Vec<Entry>,
+    exporter: Arc<ExporterMetrics>,
+    /// Name of the collector exporting each metric family
+    owners: Arc<HashMap<String, &'static str>>,
+}
+
+impl Collectors {
+    /// Creates all collectors, configured as in `config`, and registers the metrics
+    /// of the enabled ones. Every run is recorded in `exporter`.
+    pub fn new(
+        config: &Config,
+        exporter: Arc<ExporterMetrics>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut collectors = Collectors {
+            entries: Vec::new(),
+            exporter,
+            owners: Arc::default(),
+        };
+        let mut owners = HashMap::new();
+        let now = Instant::now();
+        for name in NAMES {
+            let mut collector = build(name)?;
+            for metric in collector.describe() {
+                for desc in metric.desc() {
+                    owners.insert(desc.fq_name.clone(), *name);
+                }
+            }
+            collector
+                .configure(&config.options(name))
+                .map_err(|e| format!("Invalid options for {} collector: {}", name, e))?;
+            collectors.entries.push(Entry::new(
+                name,
+                collector,
+                config.interval(name),
+                config.timeout(name),
+                now,
+            ));
+            collectors.set_enabled(name, config.is_enabled(name))?;
+        }
+        collectors.owners = Arc::new(owners);
+        Ok(collectors)
+    }
+
+    /// Applies a new configuration to the existing collectors, keeping their metric
+    /// values. Every collector's options are validated first, so nothing changes
+    /// when one of them is invalid.
+    ///
+    /// Runs in progress are waited for, up to their timeout. A collector stuck in a
+    /// timed out run can't be reconfigured.
+    pub async fn reconfigure(&mut self, config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+        for name in NAMES {
+            validate_options(name, &config.options(name))
+                .map_err(|e| format!("Invalid options for {} collector: {}", name, e))?;
+        }
+
+        let instances: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| Arc::clone(&entry.instance))
+            .collect();
+        let mut guards = loop {
+            let busy = match try_lock_all(&instances) {
+                Ok(guards) => break guards,
+                Err(busy) => &self.entries[busy],
+            };
+            // The run of a collector that is no longer running has timed out
+            if !busy.running || busy.started + busy.timeout <= Instant::now() {
+                return Err(
+                    format!("The {} collector is stuck in a timed out run", busy.name).into(),
+                );
+            }
+            tokio::time::sleep(RUN_POLL_INTERVAL).await;
+        };
+
+        // Enable and disable collectors first, rolling back when one of them fails
+        let mut toggled: Vec<usize> = Vec::new();
+        for (i, (entry, instance)) in self.entries.iter().zip(&guards).enumerate() {
+            let enabled = config.is_enabled(entry.name);
+            if entry.enabled == enabled {
+                continue;
+            }
+            if let Err(e) = register(&entry.registry, instance.collector.as_ref(), enabled) {
+                for &j in &toggled {
+                    let entry = &self.entries[j];
+                    let collector = guards[j].collector.as_ref();
+                    if let Err(e) = register(&entry.registry, collector, entry.enabled) {
+                        error!("Failed to restore the {} collector: {}", entry.name, e);
+                    }
+                }
+                return Err(e);
+            }
+            toggled.push(i);
+        }
+
+        let now = Instant::now();
+        for (entry, instance) in self.entries.iter_mut().zip(&mut guards) {
+            // Validated above, so this doesn't fail
+            instance.collector.configure(&config.options(entry.name))?;
+
+            let interval = config.interval(entry.name);
+            if entry.interval != interval {
+                entry.interval = interval;
+                entry.scheduled = now;
+                entry.due = now + jitter(interval);
+            }
+            entry.timeout = config.timeout(entry.name);
+
+            let enabled = config.is_enabled(entry.name);
+            if entry.enabled != enabled {
+                entry.enabled = enabled;
+                entry.update_families();
+            }
+        }
+        Ok(())
+    }
+
+    /// Enables or disables a collector, registering or unregistering its metrics.
+    /// Only used while the collectors are created, before any of them runs, as
+    /// [`Collectors::reconfigure`] changes them afterwards.
+    ///
+    /// Metric values are kept while a collector is disabled, so counters carry on
+    /// where they left off once it is enabled again.
+    fn set_enabled(&mut self, name: &str, enabled: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(entry) = self.entries.iter_mut().find(|entry| entry.name == name) else {
+            return Err(format!("Unknown collector: {}", name).into());
+        };
+        if entry.enabled == enabled {
+            return Ok(());
+        }
+
+        let Some(instance) = try_lock(&entry.instance) else {
+            return Err(format!("The {} collector is still running", name).into());
+        };
+        register(&entry.registry, instance.collector.as_ref(), enabled)?;
+        drop(instance);
+        entry.enabled = enabled;
+        entry.update_families();
+
+        Ok(())
+    }
+
+    /// Name of the collector exporting each metric family
+    pub fn owners(&self) -> Arc<HashMap<String, &'static str>> {
+        Arc::clone(&self.owners)
+    }
+
+    /// The metrics of every enabled collector, as gathered after its last finished
+    /// run. A collector that is still running keeps its previous metrics.
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.entries
+            .iter()
+            .flat_map(|entry| entry.families.iter().cloned())
+            .collect()
+    }
+
+    /// When the last successful run of an enabled collector ended, `None` when none
+    /// has succeeded yet
+    pub fn last_success(&self) -> Option<Instant> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.enabled)
+            .filter_map(|entry| entry.last_success)
+            .max()
+    }
+
+    /// When the next enabled collector that isn't running is due, `None` when there
+    /// is none
+    pub fn next_due(&self) -> Option<Instant> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.enabled && !entry.running)
+            .map(Entry::next_run)
+            .min()
+    }
+
+    /// Starts the enabled collectors that are due at `now`, adding their runs to
+    /// `runs`. Their next run is scheduled once they finish.
+    pub fn start_due(&mut self, now: Instant, runs: &mut JoinSet<Completion>) {
+        self.start(now, true, runs, |_| true);
+    }
+
+    /// Starts every enabled collector, regardless of its schedule, adding their runs
+    /// to `runs`. Collectors that are running or backing off after repeated failures
+    /// are skipped.
+    pub fn start_all(&mut self, now: Instant, runs: &mut JoinSet<Completion>) {
+        self.start(now, false, runs, |_| true);
+    }
+
+    /// Starts the enabled collectors kept by `filter` whose last run ended at least
+    /// `min_age` ago, adding their runs to `runs`. The others serve the metrics of
+    /// their last run. Collectors that are running or backing off are skipped.
+    pub fn start_stale(
+        &mut self,
+        now: Instant,
+        filter: &Filter,
+        min_age: Duration,
+        runs: &mut JoinSet<Completion>,
+    ) {
+        self.start(now, false, runs, |entry| {
+            filter.includes(entry.name)
+                && entry
+                    .last_run
+                    .is_none_or(|last_run| now.saturating_duration_since(last_run) >= min_age)
+        });
+    }
+
+    fn start(
+        &mut self,
+        now: Instant,
+        on_schedule: bool,
+        runs: &mut JoinSet<Completion>,
+        selected: impl Fn(&Entry) -> bool,
+    ) {
+        for (i, entry) in self.entries.iter_mut().enumerate() {
+            if entry.is_runnable(now, on_schedule) && selected(entry) {
+                entry.spawn(i, now, on_schedule, runs);
+            }
+        }
+    }
+
+    /// Whether a collector kept by `filter` is running
+    pub fn is_running(&self, filter: &Filter) -> bool {
+        self.entries
+            .iter()
+            .any(|entry| entry.running && filter.includes(entry.name))
+    }
+
+    /// Records how a run ended and, unless the collector may still be updating its
+    /// metrics, gathers them. Returns the name of the collector.
+    pub fn finish(&mut self, completion: Completion, now: Instant) -> &'static str {
+        let entry = &mut self.entries[completion.index];
+        entry.running = false;
+        entry.last_run = Some(now);
+        entry.record(&completion.outcome, now);
+        self.exporter.observe_collection(
+            entry.name,
+            completion.duration,
+            &completion.outcome,
+            entry.failures,
+        );
+        if completion.on_schedule {
+            entry.reschedule(now);
+        }
+
+        // A run that timed out goes on in the background, its metrics are only
+        // gathered once a later run finishes
+        if !matches!(completion.outcome, Outcome::TimedOut | Outcome::Busy) {
+            entry.update_families();
+        }
+        entry.name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use prometheus::{Gauge, Opts};
+
+    use super::*;
+
+    /// Sets its gauge to the number of runs, taking `delay` on the second run
+    struct TestCollector {
+        name: &'static str,
+        runs: Gauge,
+        delay: Duration,
+    }
+
+    impl Collector for TestCollector {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+            vec![Box::new(self.runs.clone())]
+        }
+
+        fn collect(&mut self, _system: &System) -> Result<(), CollectError> {
+            self.runs.inc();
+            if self.runs.get() == 2.0 {
+                thread::sleep(self.delay);
+            }
+            Ok(())
+        }
+    }
+
+    fn collectors(delays: &[(&'static str, Duration)]) -> Collectors {
+        let exporter = ExporterMetrics::new(&Registry::new()).unwrap();
+        let mut collectors = Collectors {
+            entries: Vec::new(),
+            exporter: Arc::new(exporter),
+            owners: Arc::default(),
+        };
+        let now = Instant::now();
+        for (name, delay) in delays {
+            let runs = Gauge::with_opts(Opts::new(format!("{}_runs", name), "Runs")).unwrap();
+            let collector = TestCollector {
+                name,
+                runs,
+                delay: *delay,
+            };
+            let entry = Entry::new(
+                name,
+                Box::new(collector),
+                Duration::from_secs(1),
+                Duration::from_millis(50),
+                now,
+            );
+            collectors.entries.push(entry);
+            collectors.set_enabled(name, true).unwrap();
+        }
+        collectors
+    }
+
+    fn runs(collectors: &Collectors, name: &str) -> Option<f64> {
+        collectors
+            .gather()
+            .iter()
+            .find(|family| family.name() == format!("{}_runs", name))
+            .map(|family| family.get_metric()[0].get_gauge().value())
+    }
+
+    /// Starts every collector and finishes the runs in the order they end
+    async fn collect(collectors: &mut Collectors) -> Vec<&'static str> {
+        let mut runs = JoinSet::new();
+        collectors.start_all(Instant::now(), &mut runs);
+        let mut finished = Vec::new();
+        while let Some(completion) = runs.join_next().await {
+            finished.push(collectors.finish(completion.unwrap(), Instant::now()));
+        }
+        finished
+    }
+
+    #[tokio::test]
+    async fn hung_collector_keeps_its_previous_metrics() {
+        let mut collectors = collectors(&[
+            ("slow", Duration::from_millis(300)),
+            ("fast", Duration::ZERO),
+        ]);
+
+        collect(&mut collectors).await;
+        assert_eq!(runs(&collectors, "slow"), Some(1.0));
+
+        // The fast collector finishes first, the slow one times out
+        assert_eq!(collect(&mut collectors).await, ["fast", "slow"]);
+        assert_eq!(runs(&collectors, "fast"), Some(2.0));
+        // The second run already counted, but it hasn't finished
+        assert_eq!(runs(&collectors, "slow"), Some(1.0));
+        assert_eq!(collectors.entries[0].failures, 1);
+
+        // Still busy with the run that timed out
+        assert_eq!(collect(&mut collectors).await.len(), 2);
+        assert_eq!(runs(&collectors, "slow"), Some(1.0));
+
+        // Backing off after two failures in a row
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(collect(&mut collectors).await, ["fast"]);
+        assert_eq!(runs(&collectors, "slow"), Some(1.0));
+
+        collectors.entries[0].retry_at = None;
+        collect(&mut collectors).await;
+        assert_eq!(runs(&collectors, "slow"), Some(3.0));
+        assert_eq!(collectors.entries[0].failures, 0);
+    }
+
+    /// Exports two gauges
+    struct PairCollector(Gauge, Gauge);
+
+    impl Collector for PairCollector {
+        fn name(&self) -> &'static str {
+            "pair"
+        }
+
+        fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+            vec![Box::new(self.0.clone()), Box::new(self.1.clone())]
+        }
+
+        fn collect(&mut self, _system: &System) -> Result<(), CollectError> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn registers_all_metrics_or_none() {
+        let first = Gauge::with_opts(Opts::new("first", "First")).unwrap();
+        let second = Gauge::with_opts(Opts::new("second", "Second")).unwrap();
+        let registry = Registry::new();
+        registry.register(Box::new(second.clone())).unwrap();
+
+        // The second gauge is already registered, so the first one must not stay
+        assert!(register(&registry, &PairCollector(first, second), true).is_err());
+        let families = registry.gather();
+        let names: Vec<&str> = families.iter().map(|family| family.name()).collect();
+        assert_eq!(names, ["second"]);
+    }
+
+    #[tokio::test]
+    async fn scrapes_run_the_stale_collectors_they_ask_for() {
+        let mut collectors = collectors(&[("cpu", Duration::ZERO), ("process", Duration::ZERO)]);
+        let min_age = Duration::from_secs(1);
+        let scrape = |collectors: &mut Collectors, query, now| {
+            let filter = Filter::from_query(Some(query)).unwrap();
+            let mut runs = JoinSet::new();
+            collectors.start_stale(now, &filter, min_age, &mut runs);
+            runs
+        };
+
+        let now = Instant::now();
+        let mut runs = scrape(&mut collectors, "collect[]=cpu", now);
+        let completion = runs.join_next().await.unwrap().unwrap();
+        assert_eq!(collectors.finish(completion, now), "cpu");
+        assert!(runs.is_empty());
+
+        // The cpu run is recent enough to be served again
+        assert!(scrape(&mut collectors, "collect[]=cpu", now + min_age / 2).is_empty());
+        let runs = scrape(&mut collectors, "exclude[]=process", now + min_age);
+        assert_eq!(runs.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn running_collectors_are_not_started_again() {
+        let mut collectors = collectors(&[("cpu", Duration::ZERO)]);
+        let mut runs = JoinSet::new();
+        let now = Instant::now();
+
+        collectors.start_due(now + Duration::from_millis(100), &mut runs);
+        assert_eq!(runs.len(), 1);
+        assert_eq!(collectors.next_due(), None);
+        collectors.start_due(now + Duration::from_millis(100), &mut runs);
+        assert_eq!(runs.len(), 1);
+
+        assert_eq!(collectors.last_success(), None);
+        let completion = runs.join_next().await.unwrap().unwrap();
+        collectors.finish(completion, now + Duration::from_millis(100));
+        assert_eq!(
+            collectors.last_success(),
+            Some(now + Duration::from_millis(100))
+        );
+        // Rescheduled one interval later, plus up to a tenth of it as jitter
+        let next_due = collectors.next_due().unwrap();
+        assert!(next_due >= now + Duration::from_millis(900));
+        assert!(next_due <= now + Duration::from_millis(1100));
+    }
+
+    #[tokio::test]
+    async fn reconfiguring_waits_for_runs_in_progress() {
+        let mut collectors = collectors(&[("slow", Duration::from_millis(30))]);
+        collect(&mut collectors).await;
+
+        // The second run takes a while but ends before its timeout
+        let mut pending = JoinSet::new();
+        collectors.start_all(Instant::now(), &mut pending);
+        collectors.reconfigure(&Config::default()).await.unwrap();
+
+        let completion = pending.join_next().await.unwrap().unwrap();
+        assert!(matches!(completion.outcome, Outcome::Success));
+        collectors.finish(completion, Instant::now());
+        assert_eq!(runs(&collectors, "slow"), Some(2.0));
+    }
+
+    #[tokio::test]
+    async fn timed_out_runs_block_reconfiguring() {
+        let mut collectors = collectors(&[("slow", Duration::from_millis(300))]);
+        collect(&mut collectors).await;
+
+        assert_eq!(collect(&mut collectors).await, ["slow"]);
+        let error = collectors
+            .reconfigure(&Config::default())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The slow collector is stuck in a timed out run"
+        );
+    }
+
+    #[test]
+    fn options_are_validated_like_configure_does() {
+        let unknown: toml::Table = toml::from_str("unknown = 1").unwrap();
+        for name in NAMES {
+            let mut collector = build(name).unwrap();
+            for options in [toml::Table::new(), unknown.clone()] {
+                let configured = collector.configure(&options).map_err(|e| e.to_string());
+                let validated = validate_options(name, &options).map_err(|e| e.to_string());
+                assert_eq!(validated, configured, "{}", name);
+            }
+        }
+
+        let exclude: toml::Table = toml::from_str("exclude = [\"lo\"]").unwrap();
+        assert!(validate_options("network", &exclude).is_ok());
+        assert!(validate_options("memory", &exclude).is_err());
+        assert!(validate_options("disk", &toml::Table::new()).is_err());
+    }
+}
@@ -0,0 +1,165 @@
+use prometheus::{CounterVec, Opts};
+use serde::Deserialize;
+use sysinfo::{Networks, System};
+
+use super::{CollectError, Collector};
+
+/// Collector for per-interface network traffic
+pub struct NetworkCollector {
+    /// Total number of bytes received, per network interface
+    network_received_total: CounterVec,
+    /// Total number of bytes transmitted, per network interface
+    network_transmitted_total: CounterVec,
+    /// Total number of packets received, per network interface
+    network_packets_received_total: CounterVec,
+    /// Total number of packets transmitted, per network interface
+    network_packets_transmitted_total: CounterVec,
+    /// Total number of errors on received packets, per network interface
+    network_errors_on_received_total: CounterVec,
+    /// Total number of errors on transmitted packets, per network interface
+    network_errors_on_transmitted_total: CounterVec,
+
+    /// Loaded on the first run, so a disabled collector never reads the interfaces
+    networks: Option<Networks>,
+    options: NetworkOptions,
+}
+
+/// Options of the network collector
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkOptions {
+    /// Interfaces to leave out, e.g. `lo`
+    exclude: Vec<String>,
+}
+
+impl NetworkCollector {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let network_received_total_opts = Opts::new(
+            "received_bytes_total",
+            "Total number of bytes received, per network interface",
+        )
+        .namespace("simon")
+        .subsystem("network");
+        let network_received_total = CounterVec::new(network_received_total_opts, &["interface"])?;
+
+        let network_transmitted_total_opts = Opts::new(
+            "transmitted_bytes_total",
+            "Total number of bytes transmitted, per network interface",
+        )
+        .namespace("simon")
+        .subsystem("network");
+        let network_transmitted_total =
+            CounterVec::new(network_transmitted_total_opts, &["interface"])?;
+
+        let network_packets_received_total_opts = Opts::new(
+            "packets_received_total",
+            "Total number of packets received, per network interface",
+        )
+        .namespace("simon")
+        .subsystem("network");
+        let network_packets_received_total =
+            CounterVec::new(network_packets_received_total_opts, &["interface"])?;
+
+        let network_packets_transmitted_total_opts = Opts::new(
+            "packets_transmitted_total",
+            "Total number of packets transmitted, per network interface",
+        )
+        .namespace("simon")
+        .subsystem("network");
+        let network_packets_transmitted_total =
+            CounterVec::new(network_packets_transmitted_total_opts, &["interface"])?;
+
+        let network_errors_on_received_total_opts = Opts::new(
+            "errors_on_received_total",
+            "Total number of errors on received packets, per network interface",
+        )
+        .namespace("simon")
+        .subsystem("network");
+        let network_errors_on_received_total =
+            CounterVec::new(network_errors_on_received_total_opts, &["interface"])?;
+
+        let network_errors_on_transmitted_total_opts = Opts::new(
+            "errors_on_transmitted_total",
+            "Total number of errors on transmitted packets, per network interface",
+        )
+        .namespace("simon")
+        .subsystem("network");
+        let network_errors_on_transmitted_total =
+            CounterVec::new(network_errors_on_transmitted_total_opts, &["interface"])?;
+
+        Ok(NetworkCollector {
+            network_received_total,
+            network_transmitted_total,
+            network_packets_received_total,
+            network_packets_transmitted_total,
+            network_errors_on_received_total,
+            network_errors_on_transmitted_total,
+            networks: None,
+            options: NetworkOptions::default(),
+        })
+    }
+
+    fn update_network_metrics(&self, interface_name: &str, network: &sysinfo::NetworkData) {
+        self.network_received_total
+            .with_label_values(&[interface_name])
+            .inc_by(network.received() as f64);
+
+        self.network_transmitted_total
+            .with_label_values(&[interface_name])
+            .inc_by(network.transmitted() as f64);
+
+        self.network_packets_received_total
+            .with_label_values(&[interface_name])
+            .inc_by(network.packets_received() as f64);
+
+        self.network_packets_transmitted_total
+            .with_label_values(&[interface_name])
+            .inc_by(network.packets_transmitted() as f64);
+
+        self.network_errors_on_received_total
+            .with_label_values(&[interface_name])
+            .inc_by(network.errors_on_received() as f64);
+
+        self.network_errors_on_transmitted_total
+            .with_label_values(&[interface_name])
+            .inc_by(network.errors_on_transmitted() as f64);
+    }
+}
+
+impl Collector for NetworkCollector {
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+        vec![
+            Box::new(self.network_received_total.clone()),
+            Box::new(self.network_transmitted_total.clone()),
+            Box::new(self.network_packets_received_total.clone()),
+            Box::new(self.network_packets_transmitted_total.clone()),
+            Box::new(self.network_errors_on_received_total.clone()),
+            Box::new(self.network_errors_on_transmitted_total.clone()),
+        ]
+    }
+
+    fn configure(&mut self, options: &toml::Table) -> Result<(), Box<dyn std::error::Error>> {
+        self.options = options.clone().try_into()?;
+        Ok(())
+    }
+
+    fn collect(&mut self, _system: &System) -> Result<(), CollectError> {
+        let mut networks = self
+            .networks
+            .take()
+            .unwrap_or_else(Networks::new_with_refreshed_list);
+        networks.refresh(false);
+        for (name, network) in networks.iter() {
+            if self.options.exclude.contains(name) {
+                continue;
+            }
+            self.update_network_metrics(name, network);
+        }
+        self.networks = Some(networks);
+        Ok(())
+    }
+}
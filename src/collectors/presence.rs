@@ -0,0 +1,430 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use prometheus::{CounterVec, GaugeVec, Opts};
+use serde::Deserialize;
+use sysinfo::System;
+use tracing::debug;
+
+use super::{dhcp, CollectError, Collector};
+
+const ARP_TABLE_PATH: &str = "/proc/net/arp";
+
+/// ARP flag set once the hardware address of an entry is resolved
+const ATF_COM: u32 = 0x2;
+
+/// Default for how long a device is remembered after it was last seen
+const FORGET_AFTER_SECS: u64 = 7 * 24 * 60 * 60;
+
+/// A resolved entry of the ARP or IPv6 neighbor table
+#[derive(Debug)]
+pub struct Neighbor {
+    /// Protocol address of the neighbor
+    pub ip: IpAddr,
+    /// Hardware address of the neighbor, lower case
+    pub mac: String,
+}
+
+/// Parses the content of `/proc/net/arp`, skipping the header and unresolved entries.
+///
+/// Lines look like `192.168.1.10 0x1 0x2 aa:bb:cc:dd:ee:ff * br-lan`.
+pub fn parse_arp_table(content: &str) -> Vec<Neighbor> {
+    content
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 4 {
+                return None;
+            }
+            let flags = u32::from_str_radix(fields[2].trim_start_matches("0x"), 16).ok()?;
+            if flags & ATF_COM == 0 || fields[3] == "00:00:00:00:00:00" {
+                return None;
+            }
+            Some(Neighbor {
+                ip: fields[0].parse().ok()?,
+                mac: fields[3].to_ascii_lowercase(),
+            })
+        })
+        .collect()
+}
+
+/// Parses the output of `ip -6 neigh show`, skipping failed and incomplete entries.
+///
+/// Lines look like `fe80::1 dev br-lan lladdr aa:bb:cc:dd:ee:ff router REACHABLE`.
+pub fn parse_ip_neigh(output: &str) -> Vec<Neighbor> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let state = fields.last()?;
+            if matches!(*state, "FAILED" | "INCOMPLETE") {
+                return None;
+            }
+            let mac = fields
+                .iter()
+                .position(|field| *field == "lladdr")
+                .and_then(|i| fields.get(i + 1))?;
+            Some(Neighbor {
+                ip: fields.first()?.parse().ok()?,
+                mac: mac.to_ascii_lowercase(),
+            })
+        })
+        .collect()
+}
+
+fn read_arp_neighbors() -> io::Result<Vec<Neighbor>> {
+    match fs::read_to_string(ARP_TABLE_PATH) {
+        Ok(content) => Ok(parse_arp_table(&content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the IPv6 neighbor table, `None` when iproute2 isn't installed.
+///
+/// There is no procfs view of the IPv6 neighbor table, so iproute2 is asked instead.
+fn read_ipv6_neighbors() -> io::Result<Option<Vec<Neighbor>>> {
+    match Command::new("ip").args(["-6", "neigh", "show"]).output() {
+        Ok(output) if output.status.success() => Ok(Some(parse_ip_neigh(
+            &String::from_utf8_lossy(&output.stdout),
+        ))),
+        Ok(_) => Ok(Some(Vec::new())),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// What we remember about a device between collection cycles
+struct Device {
+    hostname: String,
+    first_seen: u64,
+    last_seen: u64,
+    online: bool,
+    /// Number of times the device came online, carried over when its hostname changes
+    sessions: u64,
+}
+
+/// Collector for the device presence metrics
+pub struct PresenceCollector {
+    /// Whether a device is currently in the neighbor tables
+    device_online: GaugeVec,
+    /// First time a device was seen, as a unix timestamp
+    device_first_seen: GaugeVec,
+    /// Last time a device was seen, as a unix timestamp
+    device_last_seen: GaugeVec,
+    /// Number of times a device came online
+    device_sessions_total: CounterVec,
+
+    /// Devices seen so far, by hardware address
+    devices: HashMap<String, Device>,
+    options: PresenceOptions,
+    /// Whether iproute2 is installed, only checked on the first collection
+    has_ip: bool,
+}
+
+/// Options of the presence collector
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PresenceOptions {
+    /// Seconds after which an absent device is forgotten and its series removed
+    forget_after: u64,
+    /// dnsmasq lease file used to look up hostnames
+    leases_file: PathBuf,
+}
+
+impl Default for PresenceOptions {
+    fn default() -> Self {
+        PresenceOptions {
+            forget_after: FORGET_AFTER_SECS,
+            leases_file: PathBuf::from(dhcp::DHCP_LEASES_PATH),
+        }
+    }
+}
+
+impl PresenceCollector {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let device_online_opts = Opts::new(
+            "device_online",
+            "Whether a device is currently in the neighbor tables",
+        )
+        .namespace("simon")
+        .subsystem("presence");
+        let device_online = GaugeVec::new(device_online_opts, &["mac", "hostname"])?;
+
+        let device_first_seen_opts = Opts::new(
+            "device_first_seen_timestamp_seconds",
+            "First time a device was seen, as a unix timestamp",
+        )
+        .namespace("simon")
+        .subsystem("presence");
+        let device_first_seen = GaugeVec::new(device_first_seen_opts, &["mac", "hostname"])?;
+
+        let device_last_seen_opts = Opts::new(
+            "device_last_seen_timestamp_seconds",
+            "Last time a device was seen, as a unix timestamp",
+        )
+        .namespace("simon")
+        .subsystem("presence");
+        let device_last_seen = GaugeVec::new(device_last_seen_opts, &["mac", "hostname"])?;
+
+        let device_sessions_total_opts = Opts::new(
+            "device_sessions_total",
+            "Number of times a device came online",
+        )
+        .namespace("simon")
+        .subsystem("presence");
+        let device_sessions_total =
+            CounterVec::new(device_sessions_total_opts, &["mac", "hostname"])?;
+
+        Ok(PresenceCollector {
+            device_online,
+            device_first_seen,
+            device_last_seen,
+            device_sessions_total,
+            devices: HashMap::new(),
+            options: PresenceOptions::default(),
+            has_ip: true,
+        })
+    }
+
+    /// Reads the neighbor tables and DHCP leases and updates the metrics
+    fn update_presence_metrics(&mut self) -> io::Result<()> {
+        let mut neighbors = read_arp_neighbors()?;
+        if self.has_ip {
+            match read_ipv6_neighbors()? {
+                Some(ipv6_neighbors) => neighbors.extend(ipv6_neighbors),
+                None => {
+                    debug!("ip not found, not reading the IPv6 neighbor table");
+                    self.has_ip = false;
+                }
+            }
+        }
+        let leases = dhcp::read_leases(&self.options.leases_file)?;
+
+        // DHCPv6 leases carry no MAC address, so fall back to matching on the address
+        let mut hostnames_by_mac = HashMap::new();
+        let mut hostnames_by_ip = HashMap::new();
+        for lease in leases {
+            if let Some(hostname) = lease.hostname {
+                hostnames_by_mac.insert(lease.mac, hostname.clone());
+                hostnames_by_ip.insert(lease.ip, hostname);
+            }
+        }
+
+        let mut present: HashMap<String, String> = HashMap::new();
+        for neighbor in neighbors {
+            let hostname = hostnames_by_mac
+                .get(&neighbor.mac)
+                .or_else(|| hostnames_by_ip.get(&neighbor.ip))
+                .cloned();
+            let entry = present.entry(neighbor.mac).or_default();
+            if let Some(hostname) = hostname {
+                *entry = hostname;
+            }
+        }
+
+        let mut devices = std::mem::take(&mut self.devices);
+        self.update_devices(&mut devices, present, unix_now());
+        self.devices = devices;
+
+        Ok(())
+    }
+
+    fn update_devices(
+        &self,
+        devices: &mut HashMap<String, Device>,
+        present: HashMap<String, String>,
+        now: u64,
+    ) {
+        let present_macs: HashSet<String> = present.keys().cloned().collect();
+        for (mac, hostname) in present {
+            let device = devices.entry(mac.clone()).or_insert_with(|| Device {
+                hostname: hostname.clone(),
+                first_seen: now,
+                last_seen: now,
+                online: false,
+                sessions: 0,
+            });
+
+            // Keep the series stable when the lease (and its hostname) expires. A new
+            // hostname moves the series, the session count carries over.
+            if !hostname.is_empty() && hostname != device.hostname {
+                self.remove_device_series(&mac, &device.hostname);
+                device.hostname = hostname;
+                if device.sessions > 0 {
+                    self.device_sessions_total
+                        .with_label_values(&[mac.as_str(), device.hostname.as_str()])
+                        .inc_by(device.sessions as f64);
+                }
+            }
+
+            let labels = [mac.as_str(), device.hostname.as_str()];
+            if !device.online {
+                device.sessions += 1;
+                self.device_sessions_total.with_label_values(&labels).inc();
+            }
+            device.online = true;
+            device.last_seen = now;
+
+            self.device_online.with_label_values(&labels).set(1.0);
+            self.device_first_seen
+                .with_label_values(&labels)
+                .set(device.first_seen as f64);
+            self.device_last_seen
+                .with_label_values(&labels)
+                .set(device.last_seen as f64);
+        }
+
+        devices.retain(|mac, device| {
+            if present_macs.contains(mac) {
+                return true;
+            }
+            if now.saturating_sub(device.last_seen) > self.options.forget_after {
+                self.remove_device_series(mac, &device.hostname);
+                return false;
+            }
+            device.online = false;
+            self.device_online
+                .with_label_values(&[mac.as_str(), device.hostname.as_str()])
+                .set(0.0);
+            true
+        });
+    }
+
+    fn remove_device_series(&self, mac: &str, hostname: &str) {
+        let labels = [mac, hostname];
+        let _ = self.device_online.remove_label_values(&labels);
+        let _ = self.device_first_seen.remove_label_values(&labels);
+        let _ = self.device_last_seen.remove_label_values(&labels);
+        let _ = self.device_sessions_total.remove_label_values(&labels);
+    }
+}
+
+impl Collector for PresenceCollector {
+    fn name(&self) -> &'static str {
+        "presence"
+    }
+
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+        vec![
+            Box::new(self.device_online.clone()),
+            Box::new(self.device_first_seen.clone()),
+            Box::new(self.device_last_seen.clone()),
+            Box::new(self.device_sessions_total.clone()),
+        ]
+    }
+
+    fn configure(&mut self, options: &toml::Table) -> Result<(), Box<dyn std::error::Error>> {
+        self.options = options.clone().try_into()?;
+        Ok(())
+    }
+
+    fn collect(&mut self, _system: &System) -> Result<(), CollectError> {
+        Ok(self.update_presence_metrics()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn present(entries: &[(&str, &str)]) -> HashMap<String, String> {
+        entries
+            .iter()
+            .map(|(mac, hostname)| (mac.to_string(), hostname.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn parses_resolved_arp_entries() {
+        let content = "\
+IP address       HW type     Flags       HW address            Mask     Device
+192.168.1.10     0x1         0x2         AA:BB:CC:DD:EE:01     *        br-lan
+192.168.1.11     0x1         0x0         00:00:00:00:00:00     *        br-lan
+192.168.1.12     0x1         0x2         aa:bb:cc:dd:ee:02     *        br-lan
+";
+        let neighbors = parse_arp_table(content);
+        assert_eq!(neighbors.len(), 2);
+        assert_eq!(neighbors[0].ip, "192.168.1.10".parse::<IpAddr>().unwrap());
+        assert_eq!(neighbors[0].mac, "aa:bb:cc:dd:ee:01");
+    }
+
+    #[test]
+    fn parses_ipv6_neighbors() {
+        let output = "\
+fe80::1 dev br-lan lladdr AA:BB:CC:DD:EE:01 router REACHABLE
+fd00::12 dev br-lan lladdr aa:bb:cc:dd:ee:02 STALE
+fd00::13 dev br-lan FAILED
+fd00::14 dev br-lan lladdr aa:bb:cc:dd:ee:04 INCOMPLETE
+";
+        let neighbors = parse_ip_neigh(output);
+        assert_eq!(neighbors.len(), 2);
+        assert_eq!(neighbors[0].mac, "aa:bb:cc:dd:ee:01");
+        assert_eq!(neighbors[1].ip, "fd00::12".parse::<IpAddr>().unwrap());
+    }
+
+    #[test]
+    fn keeps_sessions_when_hostname_appears() {
+        let collector = PresenceCollector::new().unwrap();
+        let mut devices = HashMap::new();
+        let mac = "aa:bb:cc:dd:ee:01";
+
+        collector.update_devices(&mut devices, present(&[(mac, "")]), 100);
+        collector.update_devices(&mut devices, present(&[]), 200);
+        collector.update_devices(&mut devices, present(&[(mac, "")]), 300);
+        // The DHCP lease shows up while the device stays online
+        collector.update_devices(&mut devices, present(&[(mac, "laptop")]), 400);
+
+        let sessions = |hostname| {
+            collector
+                .device_sessions_total
+                .with_label_values(&[mac, hostname])
+                .get()
+        };
+        assert_eq!(sessions("laptop"), 2.0);
+        assert_eq!(
+            collector
+                .device_online
+                .with_label_values(&[mac, "laptop"])
+                .get(),
+            1.0
+        );
+        assert!(collector
+            .device_online
+            .remove_label_values(&[mac, ""])
+            .is_err());
+
+        collector.update_devices(&mut devices, present(&[]), 500);
+        collector.update_devices(&mut devices, present(&[(mac, "laptop")]), 600);
+        assert_eq!(sessions("laptop"), 3.0);
+    }
+
+    #[test]
+    fn forgets_absent_devices() {
+        let collector = PresenceCollector::new().unwrap();
+        let mut devices = HashMap::new();
+        let mac = "aa:bb:cc:dd:ee:01";
+
+        collector.update_devices(&mut devices, present(&[(mac, "phone")]), 100);
+        collector.update_devices(&mut devices, present(&[]), 100 + FORGET_AFTER_SECS + 1);
+
+        assert!(devices.is_empty());
+        assert!(collector
+            .device_sessions_total
+            .remove_label_values(&[mac, "phone"])
+            .is_err());
+    }
+}
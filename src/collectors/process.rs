@@ -0,0 +1,212 @@
+use prometheus::{CounterVec, GaugeVec, Opts};
+use serde::Deserialize;
+use sysinfo::{ProcessRefreshKind, RefreshKind, System};
+
+use super::{CollectError, Collector};
+
+/// Collector for per-process resource usage, aggregated by process name
+pub struct ProcessCollector {
+    /// CPU usage per process (aggregated by name)
+    process_cpu_usage: GaugeVec,
+
+    /// Start time per process (earliest start time by name)
+    process_start_time: GaugeVec,
+    /// Runtime per process (max runtime by name)
+    process_runtime: GaugeVec,
+
+    /// Memory usage per process (aggregated by name)
+    process_memory: GaugeVec,
+    /// Virtual memory usage per process (aggregated by name)
+    process_virtual_memory: GaugeVec,
+
+    /// Disk read per process (aggregated by name)
+    process_disk_read_total: CounterVec,
+    /// Disk write per process (aggregated by name)
+    process_disk_write_total: CounterVec,
+
+    options: ProcessOptions,
+}
+
+/// Options of the process collector
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProcessOptions {
+    /// Process names to leave out
+    exclude: Vec<String>,
+}
+
+impl ProcessCollector {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let process_memory_opts = Opts::new(
+            "memory_bytes",
+            "Memory usage per process (aggregated by name)",
+        )
+        .namespace("simon")
+        .subsystem("process");
+        let process_memory = GaugeVec::new(process_memory_opts, &["name"])?;
+
+        let process_virtual_memory_opts = Opts::new(
+            "virtual_memory_bytes",
+            "Virtual memory usage per process (aggregated by name)",
+        )
+        .namespace("simon")
+        .subsystem("process");
+        let process_virtual_memory = GaugeVec::new(process_virtual_memory_opts, &["name"])?;
+
+        let process_start_time_opts = Opts::new(
+            "start_time_seconds",
+            "Start time per process (earliest start time by name)",
+        )
+        .namespace("simon")
+        .subsystem("process");
+        let process_start_time = GaugeVec::new(process_start_time_opts, &["name"])?;
+
+        let process_runtime_opts = Opts::new(
+            "runtime_seconds",
+            "Runtime per process (max runtime by name)",
+        )
+        .namespace("simon")
+        .subsystem("process");
+        let process_runtime = GaugeVec::new(process_runtime_opts, &["name"])?;
+
+        let process_cpu_usage_opts = Opts::new(
+            "cpu_usage_percentage",
+            "CPU usage per process (aggregated by name)",
+        )
+        .namespace("simon")
+        .subsystem("process");
+        let process_cpu_usage = GaugeVec::new(process_cpu_usage_opts, &["name"])?;
+
+        let process_disk_read_total_opts = Opts::new(
+            "disk_read_bytes_total",
+            "Disk read per process (aggregated by name)",
+        )
+        .namespace("simon")
+        .subsystem("process");
+        let process_disk_read_total = CounterVec::new(process_disk_read_total_opts, &["name"])?;
+
+        let process_disk_write_total_opts = Opts::new(
+            "disk_write_bytes_total",
+            "Disk write per process (aggregated by name)",
+        )
+        .namespace("simon")
+        .subsystem("process");
+        let process_disk_write_total = CounterVec::new(process_disk_write_total_opts, &["name"])?;
+
+        Ok(ProcessCollector {
+            process_memory,
+            process_virtual_memory,
+            process_start_time,
+            process_runtime,
+            process_cpu_usage,
+            process_disk_read_total,
+            process_disk_write_total,
+            options: ProcessOptions::default(),
+        })
+    }
+
+    fn reset_process_metrics(&self) {
+        // Reset all process gauge metrics to 0
+        self.process_cpu_usage.reset();
+        self.process_memory.reset();
+        self.process_virtual_memory.reset();
+        self.process_start_time.reset();
+        self.process_runtime.reset();
+    }
+
+    fn update_process_metrics(&self, name: &str, process: &sysinfo::Process) {
+        // Get current values for aggregation (since we reset at start of cycle)
+        let current_cpu = self.process_cpu_usage.with_label_values(&[name]).get();
+        let current_memory = self.process_memory.with_label_values(&[name]).get();
+        let current_virtual_memory = self.process_virtual_memory.with_label_values(&[name]).get();
+        let current_start_time = self.process_start_time.with_label_values(&[name]).get();
+        let current_run_time = self.process_runtime.with_label_values(&[name]).get();
+
+        // Sum CPU usage, memory, virtual memory (aggregating across processes with same name)
+        self.process_cpu_usage
+            .with_label_values(&[name])
+            .set(current_cpu + process.cpu_usage() as f64);
+
+        self.process_memory
+            .with_label_values(&[name])
+            .set(current_memory + process.memory() as f64);
+
+        self.process_virtual_memory
+            .with_label_values(&[name])
+            .set(current_virtual_memory + process.virtual_memory() as f64);
+
+        // Add disk I/O bytes for this process (delta values)
+        let disk_usage = process.disk_usage();
+        self.process_disk_read_total
+            .with_label_values(&[name])
+            .inc_by(disk_usage.read_bytes as f64);
+
+        self.process_disk_write_total
+            .with_label_values(&[name])
+            .inc_by(disk_usage.written_bytes as f64);
+
+        // Use min for start_time (earliest start time for this process name)
+        let new_start_time = if current_start_time == 0.0 {
+            process.start_time() as f64
+        } else {
+            current_start_time.min(process.start_time() as f64)
+        };
+        self.process_start_time
+            .with_label_values(&[name])
+            .set(new_start_time);
+
+        // Use max for run_time (longest running time for this process name)
+        let new_run_time = current_run_time.max(process.run_time() as f64);
+        self.process_runtime
+            .with_label_values(&[name])
+            .set(new_run_time);
+    }
+}
+
+impl Collector for ProcessCollector {
+    fn name(&self) -> &'static str {
+        "process"
+    }
+
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+        vec![
+            Box::new(self.process_memory.clone()),
+            Box::new(self.process_virtual_memory.clone()),
+            Box::new(self.process_start_time.clone()),
+            Box::new(self.process_runtime.clone()),
+            Box::new(self.process_cpu_usage.clone()),
+            Box::new(self.process_disk_read_total.clone()),
+            Box::new(self.process_disk_write_total.clone()),
+        ]
+    }
+
+    fn configure(&mut self, options: &toml::Table) -> Result<(), Box<dyn std::error::Error>> {
+        self.options = options.clone().try_into()?;
+        Ok(())
+    }
+
+    fn refresh_kind(&self) -> RefreshKind {
+        RefreshKind::nothing().with_processes(
+            ProcessRefreshKind::nothing()
+                .with_cpu()
+                .with_memory()
+                .with_disk_usage(),
+        )
+    }
+
+    fn collect(&mut self, system: &System) -> Result<(), CollectError> {
+        // Reset process gauge metrics at the start of each collection cycle
+        self.reset_process_metrics();
+        // Update process metrics (aggregated by name)
+        for process in system.processes().values() {
+            let Some(name) = process.name().to_str() else {
+                continue;
+            };
+            if self.options.exclude.iter().any(|exclude| exclude == name) {
+                continue;
+            }
+            self.update_process_metrics(name, process);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,64 @@
+use prometheus::{Gauge, Opts};
+use sysinfo::{MemoryRefreshKind, RefreshKind, System};
+
+use super::{CollectError, Collector};
+
+/// Collector for swap usage
+pub struct SwapCollector {
+    /// Total swap memory in bytes
+    swap_total: Gauge,
+    /// Free swap memory in bytes
+    swap_free: Gauge,
+    /// Used swap memory in bytes
+    swap_used: Gauge,
+}
+
+impl SwapCollector {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let swap_total_opts = Opts::new("total_bytes", "Total swap memory in bytes")
+            .namespace("simon")
+            .subsystem("swap");
+        let swap_total = Gauge::with_opts(swap_total_opts)?;
+
+        let swap_free_opts = Opts::new("free_bytes", "Free swap memory in bytes")
+            .namespace("simon")
+            .subsystem("swap");
+        let swap_free = Gauge::with_opts(swap_free_opts)?;
+
+        let swap_used_opts = Opts::new("used_bytes", "Used swap memory in bytes")
+            .namespace("simon")
+            .subsystem("swap");
+        let swap_used = Gauge::with_opts(swap_used_opts)?;
+
+        Ok(SwapCollector {
+            swap_total,
+            swap_free,
+            swap_used,
+        })
+    }
+}
+
+impl Collector for SwapCollector {
+    fn name(&self) -> &'static str {
+        "swap"
+    }
+
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+        vec![
+            Box::new(self.swap_total.clone()),
+            Box::new(self.swap_free.clone()),
+            Box::new(self.swap_used.clone()),
+        ]
+    }
+
+    fn refresh_kind(&self) -> RefreshKind {
+        RefreshKind::nothing().with_memory(MemoryRefreshKind::nothing().with_swap())
+    }
+
+    fn collect(&mut self, system: &System) -> Result<(), CollectError> {
+        self.swap_total.set(system.total_swap() as f64);
+        self.swap_free.set(system.free_swap() as f64);
+        self.swap_used.set(system.used_swap() as f64);
+        Ok(())
+    }
+}
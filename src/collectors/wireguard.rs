@@ -0,0 +1,304 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::process::Command;
+
+use prometheus::{CounterVec, GaugeVec, Opts};
+use sysinfo::System;
+
+use super::{CollectError, Collector};
+
+/// A WireGuard peer, as listed by `wg show all dump`
+#[derive(Debug)]
+pub struct Peer {
+    /// Interface the peer belongs to
+    pub interface: String,
+    /// Base64 public key of the peer
+    pub public_key: String,
+    /// Last known endpoint, `None` until the peer connected once
+    pub endpoint: Option<String>,
+    /// Comma separated list of allowed IPs
+    pub allowed_ips: String,
+    /// Unix timestamp of the latest handshake, 0 if there was none
+    pub latest_handshake: u64,
+    /// Bytes received from the peer
+    pub transfer_rx: u64,
+    /// Bytes sent to the peer
+    pub transfer_tx: u64,
+}
+
+/// Parses the output of `wg show all dump`.
+///
+/// Every interface line is followed by one line per peer. Interface lines have five
+/// tab separated fields, peer lines have nine:
+/// `interface public-key preshared-key endpoint allowed-ips latest-handshake transfer-rx transfer-tx persistent-keepalive`.
+/// Missing values are printed as `(none)`.
+pub fn parse_dump(output: &str) -> Vec<Peer> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields.len() != 9 {
+                return None;
+            }
+            let endpoint = Some(fields[3])
+                .filter(|endpoint| *endpoint != "(none)")
+                .map(str::to_string);
+            let allowed_ips = match fields[4] {
+                "(none)" => String::new(),
+                allowed_ips => allowed_ips.to_string(),
+            };
+            Some(Peer {
+                interface: fields[0].to_string(),
+                public_key: fields[1].to_string(),
+                endpoint,
+                allowed_ips,
+                latest_handshake: fields[5].parse().ok()?,
+                transfer_rx: fields[6].parse().ok()?,
+                transfer_tx: fields[7].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+fn read_peers() -> io::Result<Vec<Peer>> {
+    let output = match Command::new("wg").args(["show", "all", "dump"]).output() {
+        Ok(output) => output,
+        // wireguard-tools is not installed, so there are no tunnels to report on
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "wg show all dump failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(parse_dump(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Collector for the WireGuard peer metrics
+pub struct WireguardCollector {
+    /// Peer endpoint and allowed IPs, always 1
+    peer_info: GaugeVec,
+    /// Time of the latest handshake with a peer, as a unix timestamp
+    peer_last_handshake: GaugeVec,
+    /// Total number of bytes received from a peer
+    peer_received_total: CounterVec,
+    /// Total number of bytes sent to a peer
+    peer_transmitted_total: CounterVec,
+
+    /// Transfer totals reported by the previous cycle, per interface and peer
+    previous_transfer: HashMap<(String, String), (u64, u64)>,
+}
+
+impl WireguardCollector {
+    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        let peer_info_opts = Opts::new("peer_info", "Peer endpoint and allowed IPs, always 1")
+            .namespace("simon")
+            .subsystem("wireguard");
+        let peer_info = GaugeVec::new(
+            peer_info_opts,
+            &["interface", "public_key", "endpoint", "allowed_ips"],
+        )?;
+
+        let peer_last_handshake_opts = Opts::new(
+            "peer_last_handshake_timestamp_seconds",
+            "Time of the latest handshake with a peer, as a unix timestamp",
+        )
+        .namespace("simon")
+        .subsystem("wireguard");
+        let peer_last_handshake =
+            GaugeVec::new(peer_last_handshake_opts, &["interface", "public_key"])?;
+
+        let peer_received_total_opts = Opts::new(
+            "peer_received_bytes_total",
+            "Total number of bytes received from a peer",
+        )
+        .namespace("simon")
+        .subsystem("wireguard");
+        let peer_received_total =
+            CounterVec::new(peer_received_total_opts, &["interface", "public_key"])?;
+
+        let peer_transmitted_total_opts = Opts::new(
+            "peer_transmitted_bytes_total",
+            "Total number of bytes sent to a peer",
+        )
+        .namespace("simon")
+        .subsystem("wireguard");
+        let peer_transmitted_total =
+            CounterVec::new(peer_transmitted_total_opts, &["interface", "public_key"])?;
+
+        Ok(WireguardCollector {
+            peer_info,
+            peer_last_handshake,
+            peer_received_total,
+            peer_transmitted_total,
+            previous_transfer: HashMap::new(),
+        })
+    }
+
+    /// Reads the peer list from `wg` and updates the metrics
+    fn update_wireguard_metrics(&mut self) -> io::Result<()> {
+        let peers = read_peers()?;
+        self.update_peers(peers);
+        Ok(())
+    }
+
+    fn update_peers(&mut self, peers: Vec<Peer>) {
+        // Peers can be removed or change endpoint, so rebuild the gauges every cycle
+        self.peer_info.reset();
+        self.peer_last_handshake.reset();
+
+        let mut seen = HashSet::new();
+        for peer in peers {
+            let labels = [peer.interface.as_str(), peer.public_key.as_str()];
+
+            self.peer_info
+                .with_label_values(&[
+                    peer.interface.as_str(),
+                    peer.public_key.as_str(),
+                    peer.endpoint.as_deref().unwrap_or_default(),
+                    peer.allowed_ips.as_str(),
+                ])
+                .set(1.0);
+            self.peer_last_handshake
+                .with_label_values(&labels)
+                .set(peer.latest_handshake as f64);
+
+            // wg reports running totals, turn them into deltas for the counters.
+            // Totals go back to zero when the interface is recreated.
+            let key = (peer.interface.clone(), peer.public_key.clone());
+            seen.insert(key.clone());
+            let (previous_rx, previous_tx) = self
+                .previous_transfer
+                .insert(key, (peer.transfer_rx, peer.transfer_tx))
+                .unwrap_or_default();
+            let received = if peer.transfer_rx >= previous_rx {
+                peer.transfer_rx - previous_rx
+            } else {
+                peer.transfer_rx
+            };
+            let transmitted = if peer.transfer_tx >= previous_tx {
+                peer.transfer_tx - previous_tx
+            } else {
+                peer.transfer_tx
+            };
+
+            self.peer_received_total
+                .with_label_values(&labels)
+                .inc_by(received as f64);
+            self.peer_transmitted_total
+                .with_label_values(&labels)
+                .inc_by(transmitted as f64);
+        }
+
+        // Drop the counters of peers and interfaces that are gone
+        self.previous_transfer.retain(|key, _| {
+            if seen.contains(key) {
+                return true;
+            }
+            let labels = [key.0.as_str(), key.1.as_str()];
+            let _ = self.peer_received_total.remove_label_values(&labels);
+            let _ = self.peer_transmitted_total.remove_label_values(&labels);
+            false
+        });
+    }
+}
+
+impl Collector for WireguardCollector {
+    fn name(&self) -> &'static str {
+        "wireguard"
+    }
+
+    fn describe(&self) -> Vec<Box<dyn prometheus::core::Collector>> {
+        vec![
+            Box::new(self.peer_info.clone()),
+            Box::new(self.peer_last_handshake.clone()),
+            Box::new(self.peer_received_total.clone()),
+            Box::new(self.peer_transmitted_total.clone()),
+        ]
+    }
+
+    fn collect(&mut self, _system: &System) -> Result<(), CollectError> {
+        Ok(self.update_wireguard_metrics()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = "\
+wg0\tcHJpdmF0ZQ==\tc2VydmVy\t51820\toff
+wg0\tcGVlcjE=\t(none)\t198.51.100.4:51820\t10.0.0.2/32\t1700000000\t1000\t2000\t25
+wg0\tcGVlcjI=\t(none)\t(none)\t(none)\t0\t0\t0\toff
+";
+
+    fn peer(public_key: &str, rx: u64, tx: u64) -> Peer {
+        Peer {
+            interface: "wg0".to_string(),
+            public_key: public_key.to_string(),
+            endpoint: None,
+            allowed_ips: String::new(),
+            latest_handshake: 0,
+            transfer_rx: rx,
+            transfer_tx: tx,
+        }
+    }
+
+    #[test]
+    fn parses_peer_lines_only() {
+        let peers = parse_dump(DUMP);
+        assert_eq!(peers.len(), 2);
+        assert_eq!(peers[0].public_key, "cGVlcjE=");
+        assert_eq!(peers[0].endpoint.as_deref(), Some("198.51.100.4:51820"));
+        assert_eq!(peers[0].allowed_ips, "10.0.0.2/32");
+        assert_eq!(peers[0].latest_handshake, 1700000000);
+        assert_eq!((peers[0].transfer_rx, peers[0].transfer_tx), (1000, 2000));
+        assert_eq!(peers[1].endpoint, None);
+        assert_eq!(peers[1].allowed_ips, "");
+    }
+
+    #[test]
+    fn counts_transfer_deltas() {
+        let mut collector = WireguardCollector::new().unwrap();
+        collector.update_peers(vec![peer("a", 100, 10)]);
+        collector.update_peers(vec![peer("a", 150, 30)]);
+        // The interface was recreated, totals start over
+        collector.update_peers(vec![peer("a", 20, 5)]);
+
+        let labels = ["wg0", "a"];
+        assert_eq!(
+            collector
+                .peer_received_total
+                .with_label_values(&labels)
+                .get(),
+            170.0
+        );
+        assert_eq!(
+            collector
+                .peer_transmitted_total
+                .with_label_values(&labels)
+                .get(),
+            35.0
+        );
+    }
+
+    #[test]
+    fn removes_peers_that_are_gone() {
+        let mut collector = WireguardCollector::new().unwrap();
+        collector.update_peers(vec![peer("a", 100, 10), peer("b", 200, 20)]);
+        collector.update_peers(vec![peer("b", 250, 25)]);
+
+        assert_eq!(collector.previous_transfer.len(), 1);
+        let labels = ["wg0", "a"];
+        assert!(collector
+            .peer_received_total
+            .remove_label_values(&labels)
+            .is_err());
+        assert!(collector
+            .peer_transmitted_total
+            .remove_label_values(&labels)
+            .is_err());
+    }
+}
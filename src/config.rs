@@ -0,0 +1,421 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Default address the HTTP server listens on
+const DEFAULT_LISTEN: &str = "0.0.0.0:9184";
+/// Default number of seconds between two runs of a collector
+const DEFAULT_INTERVAL: u64 = 5;
+/// Default number of seconds a scrape-time collection is reused by later scrapes
+const DEFAULT_MIN_AGE: u64 = 1;
+/// Default size in bytes from which responses are compressed
+const DEFAULT_COMPRESSION_MIN_SIZE: usize = 1024;
+
+/// Exporter configuration, read from a TOML file.
+///
+/// ```toml
+/// listen = ["0.0.0.0:9184"]
+/// admin = false
+/// compression_min_size = 1024
+/// mode = "background"
+/// interval = 5
+///
+/// [collectors.firewall]
+/// enabled = false
+///
+/// [collectors.cpu]
+/// interval = 1
+///
+/// [collectors.presence]
+/// interval = 30
+/// timeout = 10
+/// forget_after = 86400
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Addresses the HTTP server listens on
+    pub listen: Vec<String>,
+    /// Whether the admin endpoints, such as `POST /-/reload`, are served
+    pub admin: bool,
+    /// Size in bytes from which responses are compressed, when the client accepts it
+    pub compression_min_size: usize,
+    /// When metrics are collected
+    pub mode: Mode,
+    /// Seconds between two runs of a collector, unless the collector sets its own
+    pub interval: u64,
+    /// In scrape mode, seconds during which the last run of a collector is served to
+    /// later scrapes instead of running it again
+    pub min_age: u64,
+    /// Per-collector settings, by collector name
+    pub collectors: BTreeMap<String, CollectorConfig>,
+}
+
+/// When metrics are collected
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// A background task runs every collector on its own interval
+    #[default]
+    Background,
+    /// The collectors a scrape asks for run when it is served, like node_exporter
+    /// does. Streams run the collectors they follow every interval.
+    Scrape,
+}
+
+/// Settings of a single collector
+#[derive(Debug, Clone, Deserialize)]
+pub struct CollectorConfig {
+    /// Whether the collector runs, collectors are enabled unless configured otherwise
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Seconds between two runs of the collector, the global interval when unset
+    #[serde(default)]
+    pub interval: Option<u64>,
+    /// Seconds after which a run of the collector is abandoned, at most half its
+    /// interval, which is also the default
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    /// Collector specific options, validated by the collector itself
+    #[serde(flatten)]
+    pub options: toml::Table,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            listen: vec![DEFAULT_LISTEN.to_string()],
+            admin: false,
+            compression_min_size: DEFAULT_COMPRESSION_MIN_SIZE,
+            mode: Mode::default(),
+            interval: DEFAULT_INTERVAL,
+            min_age: DEFAULT_MIN_AGE,
+            collectors: BTreeMap::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from `path`, or the defaults when there is no file,
+    /// then applies the environment variable overrides.
+    pub fn load(path: Option<&Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = match path {
+            Some(path) => {
+                let content = fs::read_to_string(path)
+                    .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                toml::from_str(&content)
+                    .map_err(|e| format!("Invalid configuration {}: {}", path.display(), e))?
+            }
+            None => Config::default(),
+        };
+        config.apply_env()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Applies the `SIMON_*` environment variables on top of the file.
+    ///
+    /// `SIMON_LISTEN` replaces the listen addresses (comma separated) and `SIMON_PORT`
+    /// replaces the port of every listen address, as documented by the OpenWrt scripts.
+    /// `SIMON_INTERVAL` sets the collection interval and `SIMON_COLLECTORS` enables
+    /// exactly the listed collectors.
+    fn apply_env(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.apply_vars(|name| env::var(name).ok())
+    }
+
+    /// Applies the `SIMON_*` variables returned by `var`, as [`Config::apply_env`]
+    fn apply_vars(
+        &mut self,
+        var: impl Fn(&str) -> Option<String>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(listen) = var("SIMON_LISTEN") {
+            self.listen = split_list(&listen);
+        }
+
+        if let Some(port) = var("SIMON_PORT") {
+            let port: u16 = port
+                .parse()
+                .map_err(|e| format!("Invalid SIMON_PORT {:?}: {}", port, e))?;
+            for listen in &mut self.listen {
+                let mut addr: SocketAddr = listen
+                    .parse()
+                    .map_err(|e| format!("Invalid listen address {:?}: {}", listen, e))?;
+                addr.set_port(port);
+                *listen = addr.to_string();
+            }
+        }
+
+        if let Some(interval) = var("SIMON_INTERVAL") {
+            self.interval = interval
+                .parse()
+                .map_err(|e| format!("Invalid SIMON_INTERVAL {:?}: {}", interval, e))?;
+        }
+
+        if let Some(collectors) = var("SIMON_COLLECTORS") {
+            let enabled = split_list(&collectors);
+            for name in crate::collectors::NAMES {
+                self.collector_mut(name).enabled = enabled.iter().any(|e| e == name);
+            }
+            for name in &enabled {
+                if !crate::collectors::NAMES.contains(&name.as_str()) {
+                    return Err(format!("Unknown collector in SIMON_COLLECTORS: {}", name).into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.listen.is_empty() {
+            return Err("At least one listen address is required".into());
+        }
+        for listen in &self.listen {
+            listen
+                .parse::<SocketAddr>()
+                .map_err(|e| format!("Invalid listen address {:?}: {}", listen, e))?;
+        }
+
+        if self.interval == 0 {
+            return Err("The collection interval must be at least one second".into());
+        }
+
+        for (name, collector) in &self.collectors {
+            if !crate::collectors::NAMES.contains(&name.as_str()) {
+                return Err(format!("Unknown collector: {}", name).into());
+            }
+            if collector.interval == Some(0) {
+                return Err(format!(
+                    "The interval of the {} collector must be at least one second",
+                    name
+                )
+                .into());
+            }
+            if collector.timeout == Some(0) {
+                return Err(format!(
+                    "The timeout of the {} collector must be at least one second",
+                    name
+                )
+                .into());
+            }
+            if let Some(timeout) = collector.timeout {
+                let interval = self.interval(name);
+                if Duration::from_secs(timeout) > interval / 2 {
+                    return Err(format!(
+                        "The timeout of the {} collector ({}s) must be at most half its \
+                         interval ({}s)",
+                        name,
+                        timeout,
+                        interval.as_secs()
+                    )
+                    .into());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn collector_mut(&mut self, name: &str) -> &mut CollectorConfig {
+        self.collectors
+            .entry(name.to_string())
+            .or_insert_with(|| CollectorConfig {
+                enabled: true,
+                interval: None,
+                timeout: None,
+                options: toml::Table::new(),
+            })
+    }
+
+    /// Whether the named collector should run
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.collectors
+            .get(name)
+            .is_none_or(|collector| collector.enabled)
+    }
+
+    /// Time between two runs of the named collector
+    pub fn interval(&self, name: &str) -> Duration {
+        let seconds = self
+            .collectors
+            .get(name)
+            .and_then(|collector| collector.interval)
+            .unwrap_or(self.interval);
+        Duration::from_secs(seconds)
+    }
+
+    /// Time after which a run of the named collector is abandoned, half its interval
+    /// unless configured, so a hung run is given up well before the next one is due
+    pub fn timeout(&self, name: &str) -> Duration {
+        self.collectors
+            .get(name)
+            .and_then(|collector| collector.timeout)
+            .map_or(self.interval(name) / 2, Duration::from_secs)
+    }
+
+    /// Longest interval among the enabled collectors, the global interval when none
+    /// is enabled
+    pub fn longest_interval(&self) -> Duration {
+        crate::collectors::NAMES
+            .iter()
+            .filter(|name| self.is_enabled(name))
+            .map(|name| self.interval(name))
+            .max()
+            .unwrap_or(Duration::from_secs(self.interval))
+    }
+
+    /// Options of the named collector, empty when it is not configured
+    pub fn options(&self, name: &str) -> toml::Table {
+        self.collectors
+            .get(name)
+            .map(|collector| collector.options.clone())
+            .unwrap_or_default()
+    }
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn parse(content: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    fn apply(config: &mut Config, vars: &[(&str, &str)]) -> Result<(), String> {
+        let vars: HashMap<_, _> = vars.iter().copied().collect();
+        config
+            .apply_vars(|name| vars.get(name).map(|value| value.to_string()))
+            .map_err(|e| e.to_string())
+    }
+
+    fn validate(content: &str) -> Result<(), String> {
+        parse(content)
+            .unwrap()
+            .validate()
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn parses_a_configuration_file() {
+        let config = parse(
+            r#"
+            listen = ["127.0.0.1:9100"]
+            mode = "scrape"
+            interval = 10
+
+            [collectors.cpu]
+            interval = 2
+
+            [collectors.presence]
+            enabled = false
+            timeout = 4
+            forget_after = 3600
+            "#,
+        )
+        .unwrap();
+        config.validate().unwrap();
+
+        assert_eq!(config.listen, ["127.0.0.1:9100"]);
+        assert_eq!(config.mode, Mode::Scrape);
+        assert_eq!(config.compression_min_size, DEFAULT_COMPRESSION_MIN_SIZE);
+        assert_eq!(config.interval("cpu"), Duration::from_secs(2));
+        assert_eq!(config.interval("memory"), Duration::from_secs(10));
+        assert_eq!(config.timeout("cpu"), Duration::from_secs(1));
+        assert_eq!(config.timeout("presence"), Duration::from_secs(4));
+        assert!(!config.is_enabled("presence"));
+        assert!(config.is_enabled("memory"));
+        assert_eq!(
+            config.options("presence").get("forget_after"),
+            Some(&toml::Value::Integer(3600))
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_fields() {
+        let error = parse("listen_address = \"0.0.0.0:9184\"").unwrap_err();
+        assert!(error.message().contains("unknown field `listen_address`"));
+    }
+
+    #[test]
+    fn rejects_invalid_configurations() {
+        assert_eq!(
+            validate("interval = 0").unwrap_err(),
+            "The collection interval must be at least one second"
+        );
+        assert_eq!(
+            validate("[collectors.disk]").unwrap_err(),
+            "Unknown collector: disk"
+        );
+        assert!(validate("listen = [\"localhost\"]")
+            .unwrap_err()
+            .starts_with("Invalid listen address \"localhost\""));
+        assert_eq!(
+            validate("listen = []").unwrap_err(),
+            "At least one listen address is required"
+        );
+        assert_eq!(
+            validate("[collectors.cpu]\ninterval = 4\ntimeout = 3").unwrap_err(),
+            "The timeout of the cpu collector (3s) must be at most half its interval (4s)"
+        );
+    }
+
+    #[test]
+    fn port_variable_replaces_every_listen_port() {
+        let mut config = parse(r#"listen = ["0.0.0.0:9184", "[::1]:9184"]"#).unwrap();
+        apply(&mut config, &[("SIMON_PORT", "9100")]).unwrap();
+        assert_eq!(config.listen, ["0.0.0.0:9100", "[::1]:9100"]);
+
+        let mut config = Config::default();
+        apply(
+            &mut config,
+            &[
+                ("SIMON_LISTEN", "127.0.0.1:80, 10.0.0.1:80"),
+                ("SIMON_PORT", "9200"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(config.listen, ["127.0.0.1:9200", "10.0.0.1:9200"]);
+
+        let error = apply(&mut Config::default(), &[("SIMON_PORT", "http")]).unwrap_err();
+        assert!(error.starts_with("Invalid SIMON_PORT \"http\""));
+    }
+
+    #[test]
+    fn collectors_variable_enables_exactly_the_listed_collectors() {
+        let mut config = parse("[collectors.presence]\nenabled = true").unwrap();
+        apply(&mut config, &[("SIMON_COLLECTORS", "cpu,memory")]).unwrap();
+        for name in crate::collectors::NAMES {
+            assert_eq!(config.is_enabled(name), ["cpu", "memory"].contains(name));
+        }
+
+        let error = apply(&mut Config::default(), &[("SIMON_COLLECTORS", "cpu,disk")]).unwrap_err();
+        assert_eq!(error, "Unknown collector in SIMON_COLLECTORS: disk");
+    }
+
+    #[test]
+    fn interval_variable_sets_the_interval() {
+        let mut config = Config::default();
+        apply(&mut config, &[("SIMON_INTERVAL", "15")]).unwrap();
+        assert_eq!(config.interval("cpu"), Duration::from_secs(15));
+    }
+}
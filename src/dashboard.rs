@@ -0,0 +1,34 @@
+use axum::{
+    extract::Path,
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
+};
+
+/// Files of the dashboard, embedded in the binary so it works without any install
+const ASSETS: &[(&str, &str, &str)] = &[
+    (
+        "dashboard.css",
+        "text/css; charset=utf-8",
+        include_str!("../assets/dashboard.css"),
+    ),
+    (
+        "dashboard.js",
+        "text/javascript; charset=utf-8",
+        include_str!("../assets/dashboard.js"),
+    ),
+];
+
+/// The dashboard, showing live metrics from the snapshot stream
+pub async fn index() -> Html<&'static str> {
+    Html(include_str!("../assets/index.html"))
+}
+
+/// A file of the dashboard, e.g. `/assets/dashboard.js`
+pub async fn asset(Path(name): Path<String>) -> Response {
+    match ASSETS.iter().find(|(asset, _, _)| *asset == name) {
+        Some((_, content_type, body)) => {
+            ([(header::CONTENT_TYPE, *content_type)], *body).into_response()
+        }
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
@@ -0,0 +1,247 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use prometheus::proto::MetricFamily;
+use prometheus::{Counter, Gauge, GaugeVec, Histogram, HistogramOpts, Opts, Registry};
+use sysinfo::{Pid, ProcessRefreshKind, ProcessesToUpdate, System};
+
+use crate::collectors::Outcome;
+
+/// Metrics about simon itself: collector runs, scrapes, series and resource usage
+pub struct ExporterMetrics {
+    /// Duration of the last run of each collector
+    collector_duration_seconds: GaugeVec,
+    /// Whether the last run of each collector completed, without panic nor timeout
+    collector_up: GaugeVec,
+    /// Number of runs in a row that failed, per collector
+    collector_failures: GaugeVec,
+    /// Whether the last run of each collector succeeded, a failed run is up but not
+    /// successful
+    collector_success: GaugeVec,
+    /// Time of the last successful run of each collector
+    collector_last_success_timestamp_seconds: GaugeVec,
+    /// Number of scrapes of the metrics endpoint
+    scrapes_total: Counter,
+    /// Time spent answering scrapes
+    scrape_duration_seconds: Histogram,
+    /// Number of series per metric family
+    series: GaugeVec,
+    /// Resident memory of the simon process
+    resident_memory_bytes: Gauge,
+    /// CPU time used by the simon process
+    cpu_seconds_total: Counter,
+    /// Process ID of simon, to read its own resource usage
+    pid: Option<Pid>,
+    /// Holds only the simon process
+    system: Mutex<System>,
+    /// CPU time of the simon process at the previous update, in milliseconds
+    previous_cpu_time: AtomicU64,
+}
+
+impl ExporterMetrics {
+    /// Creates the metrics and registers them with `registry`
+    pub fn new(registry: &Registry) -> Result<Self, Box<dyn std::error::Error>> {
+        let collector_duration_seconds = GaugeVec::new(
+            Opts::new(
+                "collector_duration_seconds",
+                "Duration of the last run of the collector in seconds",
+            )
+            .namespace("simon")
+            .subsystem("exporter"),
+            &["collector"],
+        )?;
+        let collector_up = GaugeVec::new(
+            Opts::new(
+                "collector_up",
+                "Whether the last run of the collector completed, without panic nor timeout",
+            )
+            .namespace("simon")
+            .subsystem("exporter"),
+            &["collector"],
+        )?;
+        let collector_failures = GaugeVec::new(
+            Opts::new(
+                "collector_failures",
+                "Number of runs in a row that failed, the collector backs off after two",
+            )
+            .namespace("simon")
+            .subsystem("exporter"),
+            &["collector"],
+        )?;
+        let collector_success = GaugeVec::new(
+            Opts::new(
+                "collector_success",
+                "Whether the last run of the collector succeeded",
+            )
+            .namespace("simon")
+            .subsystem("exporter"),
+            &["collector"],
+        )?;
+        let collector_last_success_timestamp_seconds = GaugeVec::new(
+            Opts::new(
+                "collector_last_success_timestamp_seconds",
+                "Unix time of the last successful run of the collector",
+            )
+            .namespace("simon")
+            .subsystem("exporter"),
+            &["collector"],
+        )?;
+        let scrapes_total = Counter::with_opts(
+            Opts::new("scrapes_total", "Total number of scrapes")
+                .namespace("simon")
+                .subsystem("exporter"),
+        )?;
+        let scrape_duration_seconds = Histogram::with_opts(
+            HistogramOpts::new(
+                "scrape_duration_seconds",
+                "Time spent answering scrapes in seconds",
+            )
+            .namespace("simon")
+            .subsystem("exporter")
+            .buckets(vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]),
+        )?;
+        let series = GaugeVec::new(
+            Opts::new(
+                "series",
+                "Number of series per metric family in the previous snapshot",
+            )
+            .namespace("simon")
+            .subsystem("exporter"),
+            &["family"],
+        )?;
+        let resident_memory_bytes = Gauge::with_opts(
+            Opts::new(
+                "resident_memory_bytes",
+                "Resident memory of the simon process in bytes",
+            )
+            .namespace("simon")
+            .subsystem("exporter"),
+        )?;
+        let cpu_seconds_total = Counter::with_opts(
+            Opts::new(
+                "cpu_seconds_total",
+                "Total CPU time used by the simon process in seconds",
+            )
+            .namespace("simon")
+            .subsystem("exporter"),
+        )?;
+        let build_info = GaugeVec::new(
+            Opts::new("build_info", "Build information of simon")
+                .namespace("simon")
+                .subsystem("exporter"),
+            &["version", "commit", "rustc"],
+        )?;
+        build_info
+            .with_label_values(&[
+                env!("CARGO_PKG_VERSION"),
+                env!("SIMON_GIT_COMMIT"),
+                env!("SIMON_RUSTC_VERSION"),
+            ])
+            .set(1.0);
+
+        registry.register(Box::new(collector_duration_seconds.clone()))?;
+        registry.register(Box::new(collector_up.clone()))?;
+        registry.register(Box::new(collector_failures.clone()))?;
+        registry.register(Box::new(collector_success.clone()))?;
+        registry.register(Box::new(collector_last_success_timestamp_seconds.clone()))?;
+        registry.register(Box::new(scrapes_total.clone()))?;
+        registry.register(Box::new(scrape_duration_seconds.clone()))?;
+        registry.register(Box::new(series.clone()))?;
+        registry.register(Box::new(resident_memory_bytes.clone()))?;
+        registry.register(Box::new(cpu_seconds_total.clone()))?;
+        registry.register(Box::new(build_info))?;
+
+        Ok(ExporterMetrics {
+            collector_duration_seconds,
+            collector_up,
+            collector_failures,
+            collector_success,
+            collector_last_success_timestamp_seconds,
+            scrapes_total,
+            scrape_duration_seconds,
+            series,
+            resident_memory_bytes,
+            cpu_seconds_total,
+            pid: sysinfo::get_current_pid().ok(),
+            system: Mutex::new(System::new()),
+            previous_cpu_time: AtomicU64::new(0),
+        })
+    }
+
+    /// Records a run of the named collector
+    pub fn observe_collection(
+        &self,
+        collector: &str,
+        duration: Duration,
+        outcome: &Outcome,
+        failures: u32,
+    ) {
+        let success = matches!(outcome, Outcome::Success);
+        let up = matches!(outcome, Outcome::Success | Outcome::Failed(_));
+
+        self.collector_duration_seconds
+            .with_label_values(&[collector])
+            .set(duration.as_secs_f64());
+        self.collector_up
+            .with_label_values(&[collector])
+            .set(if up { 1.0 } else { 0.0 });
+        self.collector_failures
+            .with_label_values(&[collector])
+            .set(failures as f64);
+        self.collector_success
+            .with_label_values(&[collector])
+            .set(if success { 1.0 } else { 0.0 });
+        if success {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            self.collector_last_success_timestamp_seconds
+                .with_label_values(&[collector])
+                .set(now.as_secs_f64());
+        }
+    }
+
+    /// Records a scrape of the metrics endpoint
+    pub fn observe_scrape(&self, duration: Duration) {
+        self.scrapes_total.inc();
+        self.scrape_duration_seconds.observe(duration.as_secs_f64());
+    }
+
+    /// Counts the series of every metric family in a snapshot
+    pub fn update_series(&self, families: &[MetricFamily]) {
+        self.series.reset();
+        for family in families {
+            self.series
+                .with_label_values(&[family.name()])
+                .set(family.get_metric().len() as f64);
+        }
+    }
+
+    /// Refreshes the memory and CPU usage of the simon process
+    pub fn update_process(&self) {
+        let Some(pid) = self.pid else {
+            return;
+        };
+        let Ok(mut system) = self.system.lock() else {
+            return;
+        };
+        system.refresh_processes_specifics(
+            ProcessesToUpdate::Some(&[pid]),
+            false,
+            ProcessRefreshKind::nothing().with_cpu().with_memory(),
+        );
+        let Some(process) = system.process(pid) else {
+            return;
+        };
+
+        self.resident_memory_bytes.set(process.memory() as f64);
+
+        let cpu_time = process.accumulated_cpu_time();
+        let previous = self.previous_cpu_time.swap(cpu_time, Ordering::Relaxed);
+        if cpu_time > previous {
+            self.cpu_seconds_total
+                .inc_by((cpu_time - previous) as f64 / 1000.0);
+        }
+    }
+}
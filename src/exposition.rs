@@ -0,0 +1,341 @@
+use std::io::{self, Write};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use prometheus::proto::MetricFamily;
+use prometheus::{Encoder, ProtobufEncoder, TextEncoder};
+
+use crate::collectors;
+use crate::openmetrics::{self, CreatedTimes};
+
+/// Exposition formats served on `/metrics`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Prometheus text format 0.0.4
+    Text,
+    /// OpenMetrics text format 1.0.0
+    OpenMetrics,
+    /// Length-delimited `io.prometheus.client.MetricFamily` protobuf messages
+    Protobuf,
+}
+
+impl Format {
+    /// Picks the format the client prefers from its `Accept` header, following the
+    /// `q` weights and skipping versions simon can't write. Falls back to the
+    /// Prometheus text format.
+    pub fn negotiate(accept: Option<&str>) -> Format {
+        let mut best = (Format::Text, 0.0);
+        for media_range in accept.unwrap_or_default().split(',') {
+            let mut params = media_range.split(';').map(str::trim);
+            let media_type = params.next().unwrap_or_default();
+            let params: Vec<(&str, &str)> = params
+                .filter_map(|param| param.split_once('='))
+                .map(|(name, value)| (name.trim(), value.trim()))
+                .collect();
+            let param = |name: &str| {
+                params
+                    .iter()
+                    .find(|(param, _)| *param == name)
+                    .map(|(_, value)| *value)
+            };
+
+            let format = match media_type {
+                // Versions this encoder doesn't write are left to the other formats
+                "application/openmetrics-text" if param("version").is_none_or(|v| v == "1.0.0") => {
+                    Format::OpenMetrics
+                }
+                // Only the delimited MetricFamily messages are supported
+                "application/vnd.google.protobuf"
+                    if param("proto").is_none_or(|p| p == "io.prometheus.client.MetricFamily")
+                        && param("encoding").is_none_or(|e| e == "delimited") =>
+                {
+                    Format::Protobuf
+                }
+                "text/plain" if param("version").is_none_or(|v| v == "0.0.4") => Format::Text,
+                "text/*" | "*/*" => Format::Text,
+                _ => continue,
+            };
+            let quality = param("q")
+                .and_then(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if quality > best.1 {
+                best = (format, quality);
+            }
+        }
+        best.0
+    }
+}
+
+/// Collectors selected by the `collect[]` and `exclude[]` scrape parameters
+#[derive(Debug, Default)]
+pub struct Filter {
+    collect: Vec<String>,
+    exclude: Vec<String>,
+    /// Leaves out simon's own metrics, which only `/metrics` serves
+    collectors_only: bool,
+}
+
+impl Filter {
+    /// Reads the filter from a query string, e.g. `collect[]=cpu&collect[]=network`
+    pub fn from_query(query: Option<&str>) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+        for (key, value) in form_urlencoded::parse(query.unwrap_or_default().as_bytes()) {
+            let list = match key.as_ref() {
+                "collect[]" => &mut filter.collect,
+                "exclude[]" => &mut filter.exclude,
+                _ => continue,
+            };
+            if !collectors::NAMES.contains(&value.as_ref()) {
+                return Err(format!("Unknown collector: {}", value));
+            }
+            list.push(value.into_owned());
+        }
+        Ok(filter)
+    }
+
+    /// Keeps only the collectors of a subsystem, `None` when there is no such subsystem
+    pub fn subsystem(name: &str) -> Option<Filter> {
+        collectors::subsystem(name).map(|collectors| Filter {
+            collect: collectors.iter().map(|name| name.to_string()).collect(),
+            exclude: Vec::new(),
+            collectors_only: true,
+        })
+    }
+
+    /// Whether the filter keeps every metric
+    pub fn is_empty(&self) -> bool {
+        self.collect.is_empty() && self.exclude.is_empty() && !self.collectors_only
+    }
+
+    /// Whether simon's own `simon_exporter_*` metrics are kept
+    pub fn includes_exporter(&self) -> bool {
+        !self.collectors_only
+    }
+
+    /// Whether the named collector's metrics are kept
+    pub fn includes(&self, collector: &str) -> bool {
+        (self.collect.is_empty() || self.collect.iter().any(|name| name == collector))
+            && !self.exclude.iter().any(|name| name == collector)
+    }
+}
+
+/// Content codings of response bodies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Identity,
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl Encoding {
+    /// Codings simon can compress with, in order of preference
+    const SUPPORTED: &[(&str, Encoding)] = &[
+        #[cfg(feature = "zstd")]
+        ("zstd", Encoding::Zstd),
+        ("gzip", Encoding::Gzip),
+    ];
+
+    /// Picks the coding the client prefers from its `Accept-Encoding` header,
+    /// following the `q` weights. A coding listed by name takes its own weight, `*`
+    /// only applies to the codings that aren't listed. Ties go to the coding simon
+    /// prefers.
+    pub fn negotiate(accept_encoding: Option<&str>) -> Encoding {
+        let codings: Vec<(&str, f32)> = accept_encoding
+            .unwrap_or_default()
+            .split(',')
+            .map(|coding| {
+                let mut params = coding.split(';').map(str::trim);
+                let coding = params.next().unwrap_or_default();
+                let quality = params
+                    .filter_map(|param| param.strip_prefix("q="))
+                    .find_map(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (coding, quality)
+            })
+            .collect();
+        let quality = |name: &str| {
+            codings
+                .iter()
+                .find(|(coding, _)| coding.eq_ignore_ascii_case(name))
+                .map(|(_, quality)| *quality)
+        };
+
+        let mut best = (Encoding::Identity, 0.0);
+        for (name, encoding) in Self::SUPPORTED {
+            let quality = quality(name).or_else(|| quality("*")).unwrap_or(0.0);
+            if quality > best.1 {
+                best = (*encoding, quality);
+            }
+        }
+        best.0
+    }
+
+    /// Value of the `Content-Encoding` header, `None` for identity
+    pub fn header_value(self) -> Option<&'static str> {
+        match self {
+            Encoding::Identity => None,
+            Encoding::Gzip => Some("gzip"),
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Compresses a response body
+    pub fn compress(self, body: Vec<u8>) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Identity => Ok(body),
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(&body)?;
+                encoder.finish()
+            }
+            #[cfg(feature = "zstd")]
+            Encoding::Zstd => zstd::encode_all(body.as_slice(), zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+}
+
+/// Encodes metric families, returning the body and its content type
+pub fn encode(
+    format: Format,
+    families: &[MetricFamily],
+    created: &CreatedTimes,
+) -> Result<(Vec<u8>, String), Box<dyn std::error::Error>> {
+    let mut buffer = vec![];
+    let content_type = match format {
+        Format::Text => {
+            let encoder = TextEncoder::new();
+            encoder.encode(families, &mut buffer)?;
+            encoder.format_type().to_string()
+        }
+        Format::OpenMetrics => {
+            openmetrics::encode(families, created, &mut buffer)?;
+            openmetrics::CONTENT_TYPE.to_string()
+        }
+        Format::Protobuf => {
+            let encoder = ProtobufEncoder::new();
+            encoder.encode(families, &mut buffer)?;
+            encoder.format_type().to_string()
+        }
+    };
+    Ok((buffer, content_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_the_preferred_format() {
+        assert_eq!(Format::negotiate(None), Format::Text);
+        assert_eq!(Format::negotiate(Some("*/*")), Format::Text);
+        assert_eq!(
+            Format::negotiate(Some(
+                "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5"
+            )),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            Format::negotiate(Some(
+                "application/openmetrics-text;q=0.5,text/plain;version=0.0.4"
+            )),
+            Format::Text
+        );
+        assert_eq!(
+            Format::negotiate(Some(
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;\
+                 encoding=delimited;q=0.7,text/plain;version=0.0.4;q=0.3"
+            )),
+            Format::Protobuf
+        );
+    }
+
+    #[test]
+    fn reads_the_filter_from_the_query() {
+        let filter = Filter::from_query(Some("collect[]=cpu&collect%5B%5D=network")).unwrap();
+        assert!(filter.includes("cpu") && filter.includes("network"));
+        assert!(!filter.includes("process"));
+        assert!(filter.includes_exporter());
+
+        let filter = Filter::from_query(Some("exclude[]=process")).unwrap();
+        assert!(filter.includes("cpu") && !filter.includes("process"));
+
+        assert!(Filter::from_query(None).unwrap().is_empty());
+        assert!(Filter::from_query(Some("collect[]=disk")).is_err());
+    }
+
+    #[test]
+    fn leaves_exporter_metrics_out_of_subsystems() {
+        let system = Filter::subsystem("system").unwrap();
+        assert!(system.includes("cpu") && system.includes("swap"));
+        assert!(!system.includes("process"));
+        assert!(!system.includes_exporter());
+
+        let process = Filter::subsystem("process").unwrap();
+        assert!(process.includes("process") && !process.includes("cpu"));
+        assert!(!process.is_empty());
+
+        assert!(Filter::subsystem("disk").is_none());
+    }
+
+    #[test]
+    fn negotiates_the_preferred_coding() {
+        assert_eq!(Encoding::negotiate(None), Encoding::Identity);
+        assert_eq!(Encoding::negotiate(Some("identity")), Encoding::Identity);
+        assert_eq!(Encoding::negotiate(Some("gzip, deflate")), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate(Some("GZIP;q=0.8")), Encoding::Gzip);
+        assert_eq!(Encoding::negotiate(Some("gzip;q=0")), Encoding::Identity);
+        #[cfg(feature = "zstd")]
+        {
+            assert_eq!(Encoding::negotiate(Some("gzip, zstd")), Encoding::Zstd);
+            assert_eq!(
+                Encoding::negotiate(Some("zstd;q=0.5, gzip")),
+                Encoding::Gzip
+            );
+        }
+    }
+
+    #[test]
+    fn applies_the_wildcard_to_unlisted_codings_only() {
+        #[cfg(feature = "zstd")]
+        assert_eq!(
+            Encoding::negotiate(Some("*;q=0.5, gzip;q=0")),
+            Encoding::Zstd
+        );
+        #[cfg(not(feature = "zstd"))]
+        assert_eq!(
+            Encoding::negotiate(Some("*;q=0.5, gzip;q=0")),
+            Encoding::Identity
+        );
+        assert_eq!(
+            Encoding::negotiate(Some("gzip;q=0.1, *;q=0")),
+            Encoding::Gzip
+        );
+        #[cfg(feature = "zstd")]
+        assert_eq!(
+            Encoding::negotiate(Some("gzip;q=0.9, *;q=0.5")),
+            Encoding::Gzip
+        );
+        assert_eq!(Encoding::negotiate(Some("*")), Encoding::SUPPORTED[0].1);
+    }
+
+    #[test]
+    fn skips_unsupported_versions_and_encodings() {
+        assert_eq!(
+            Format::negotiate(Some("application/openmetrics-text;version=2.0.0")),
+            Format::Text
+        );
+        assert_eq!(
+            Format::negotiate(Some(
+                "application/openmetrics-text;version=0.0.1,\
+                 application/vnd.google.protobuf;encoding=delimited;q=0.5"
+            )),
+            Format::Protobuf
+        );
+        assert_eq!(
+            Format::negotiate(Some("application/vnd.google.protobuf;encoding=text")),
+            Format::Text
+        );
+    }
+}
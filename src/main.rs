@@ -1,60 +1,59 @@
-mod metrics;
+mod api;
+mod cli;
+mod collectors;
+mod config;
+mod dashboard;
+mod exporter;
+mod exposition;
+mod openmetrics;
 mod state;
 
+use std::convert::Infallible;
+use std::future::IntoFuture;
+use std::io::Write;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 
+use api::Subscription;
 use axum::{
-    extract::State,
-    http::StatusCode,
-    response::{Html, IntoResponse, Response},
-    routing::get,
+    extract::{
+        ws::{Message, WebSocket, WebSocketUpgrade},
+        Path, RawQuery, State,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Json, Response,
+    },
+    routing::{get, post},
     Router,
 };
-use prometheus::{Encoder, TextEncoder};
+use clap::Parser;
+use tokio::sync::broadcast;
 use tracing::{debug, error, info};
 
+use cli::{Cli, Command};
+use config::{Config, Mode};
+use exposition::{Encoding, Filter, Format};
 use state::AppState;
 
-async fn home() -> Html<String> {
-    Html(format!(
-        r#"
-        <!DOCTYPE html>
-        <html lang="en">
-        <head>
-            <meta charset="UTF-8">
-            <meta name="viewport" content="width=device-width, initial-scale=1.0">
-            <title>System Metrics Exporter</title>
-            <style>
-                body {{ font-family: Arial, sans-serif; line-height: 1.6; padding: 20px; }}
-                h1 {{ color: #333; }}
-                a {{ color: #0066cc; }}
-            </style>
-        </head>
-        <body>
-            <h1>Welcome to System Metrics Exporter</h1>
-            <p>This service exports various system metrics in Prometheus format.</p>
-            <p>You can access the metrics at: <a href="/metrics">/metrics</a></p>
-            <h2>Available Metrics:</h2>
-            <ul>
-                <li>CPU usage per core</li>
-                <li>Memory usage</li>
-                <li>Network usage (received and transmitted bytes per interface)</li>
-                <li>Disk I/O (read and write bytes per disk)</li>
-            </ul>
-            <p>These metrics can be scraped by Prometheus and visualized using tools like Grafana.</p>
-        </body>
-        </html>
-        "#
-    ))
-}
-
-async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+async fn metrics(
+    State(state): State<Arc<AppState>>,
+    RawQuery(query): RawQuery,
+    headers: HeaderMap,
+) -> impl IntoResponse {
     debug!("Metrics endpoint called");
 
+    let filter = match Filter::from_query(query.as_deref()) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{}\n", e)).into_response(),
+    };
+
     let start_time = Instant::now();
-    let result = try_get_metrics(state);
+    let result = try_get_metrics(Arc::clone(&state), &filter, &headers).await;
     let total_duration = start_time.elapsed();
+    state.exporter.observe_scrape(total_duration);
     info!("Total request processing time: {:?}", total_duration);
 
     match result {
@@ -70,46 +69,369 @@ async fn metrics(State(state): State<Arc<AppState>>) -> impl IntoResponse {
     }
 }
 
-fn try_get_metrics(state: Arc<AppState>) -> Result<Response, Box<dyn std::error::Error>> {
-    // Encode the metrics as a string
-    let mut buffer = vec![];
-    let encoder = TextEncoder::new();
-    encoder.encode(&state.registry.gather(), &mut buffer)?;
+/// Metrics of a single subsystem or collector, e.g. `/metrics/system` or
+/// `/metrics/process`. simon's own metrics are only served on `/metrics`.
+async fn subsystem_metrics(
+    State(state): State<Arc<AppState>>,
+    Path(subsystem): Path<String>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    debug!("Metrics endpoint called for {}", subsystem);
+
+    let Some(filter) = Filter::subsystem(&subsystem) else {
+        return (
+            StatusCode::NOT_FOUND,
+            format!("Unknown subsystem: {}\n", subsystem),
+        )
+            .into_response();
+    };
+
+    let start_time = Instant::now();
+    let result = try_get_metrics(Arc::clone(&state), &filter, &headers).await;
+    state.exporter.observe_scrape(start_time.elapsed());
+
+    match result {
+        Ok(response) => response,
+        Err(e) => {
+            error!("Error generating metrics: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Error generating metrics",
+            )
+                .into_response()
+        }
+    }
+}
+
+async fn try_get_metrics(
+    state: Arc<AppState>,
+    filter: &Filter,
+    headers: &HeaderMap,
+) -> Result<Response, Box<dyn std::error::Error>> {
+    state.collect_for_scrape(filter).await?;
+
+    // Serve the collectors and the format the scraper asks for
+    let snapshot = state.snapshot();
+    let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok());
+    let (buffer, content_type) = exposition::encode(
+        Format::negotiate(accept),
+        &snapshot.select(filter),
+        &snapshot.created,
+    )?;
 
-    // Convert the format_type to a String
-    let content_type = encoder.format_type().to_string();
+    // Compress large bodies for clients that accept it, small ones aren't worth it
+    let accept_encoding = headers
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok());
+    let encoding = if buffer.len() >= state.compression_min_size() {
+        Encoding::negotiate(accept_encoding)
+    } else {
+        Encoding::Identity
+    };
+    // Compressing a large body takes a while, keep it off the thread serving requests
+    let buffer = match encoding {
+        Encoding::Identity => buffer,
+        _ => tokio::task::spawn_blocking(move || encoding.compress(buffer)).await??,
+    };
 
-    Ok((
+    let mut response = (
         StatusCode::OK,
-        [(axum::http::header::CONTENT_TYPE, content_type)],
+        [
+            (header::CONTENT_TYPE, content_type),
+            (header::VARY, "Accept, Accept-Encoding".to_string()),
+        ],
         buffer,
     )
-        .into_response())
+        .into_response();
+    if let Some(value) = encoding.header_value() {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_ENCODING, HeaderValue::from_static(value));
+    }
+    Ok(response)
 }
 
-#[tokio::main(flavor = "current_thread")]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing
-    tracing_subscriber::fmt::init();
+/// The latest collected values as JSON
+async fn api_snapshot(State(state): State<Arc<AppState>>, RawQuery(query): RawQuery) -> Response {
+    debug!("Snapshot endpoint called");
+
+    let filter = match Filter::from_query(query.as_deref()) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{}\n", e)).into_response(),
+    };
+    if let Err(e) = state.collect_for_scrape(&filter).await {
+        error!("Error collecting metrics: {}", e);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Error collecting metrics",
+        )
+            .into_response();
+    }
+    Json(api::snapshot(&state.snapshot(), &filter)).into_response()
+}
+
+/// Streams the snapshot of every collection cycle as Server-Sent Events
+async fn api_stream(State(state): State<Arc<AppState>>, RawQuery(query): RawQuery) -> Response {
+    debug!("Stream endpoint called");
+
+    let filter = match Filter::from_query(query.as_deref()) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{}\n", e)).into_response(),
+    };
+    let subscription = Subscription::new(state, filter);
+    let events = futures_util::stream::unfold(subscription, |mut subscription| async move {
+        let snapshot = subscription.next().await?;
+        let event = Event::default()
+            .event("snapshot")
+            .data(snapshot.to_string());
+        Some((Ok::<_, Infallible>(event), subscription))
+    });
+    Sse::new(events)
+        .keep_alive(KeepAlive::default())
+        .into_response()
+}
+
+/// Streams the snapshot of every collection cycle over a WebSocket
+async fn api_websocket(
+    State(state): State<Arc<AppState>>,
+    RawQuery(query): RawQuery,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    debug!("WebSocket endpoint called");
+
+    let filter = match Filter::from_query(query.as_deref()) {
+        Ok(filter) => filter,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{}\n", e)).into_response(),
+    };
+    let subscription = Subscription::new(state, filter);
+    upgrade.on_upgrade(|socket| stream_to_websocket(socket, subscription))
+}
+
+/// Sends snapshots until the client goes away or simon shuts down. Messages from the
+/// client are ignored.
+async fn stream_to_websocket(mut socket: WebSocket, mut subscription: Subscription) {
+    loop {
+        tokio::select! {
+            snapshot = subscription.next() => {
+                let Some(snapshot) = snapshot else {
+                    let _ = socket.send(Message::Close(None)).await;
+                    return;
+                };
+                if socket.send(Message::Text(snapshot.to_string().into())).await.is_err() {
+                    return;
+                }
+            }
+            message = socket.recv() => match message {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Liveness: answers as long as the process serves requests
+async fn healthz() -> &'static str {
+    "OK\n"
+}
+
+/// Readiness: fails while metrics are missing or stale, so a supervisor can restart simon
+async fn ready(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.readiness() {
+        Ok(()) => (StatusCode::OK, "Ready\n".to_string()),
+        Err(reason) => {
+            debug!("Not ready: {}", reason);
+            (StatusCode::SERVICE_UNAVAILABLE, format!("{}\n", reason))
+        }
+    }
+}
+
+/// Reloads the configuration, answering with the error when it is invalid
+async fn reload(State(state): State<Arc<AppState>>) -> impl IntoResponse {
+    match state.reload().await {
+        Ok(()) => (StatusCode::OK, "Configuration reloaded\n".to_string()),
+        Err(e) => {
+            error!("Failed to reload configuration: {}", e);
+            (
+                StatusCode::BAD_REQUEST,
+                format!("Failed to reload configuration: {}\n", e),
+            )
+        }
+    }
+}
 
+/// Reloads the configuration every time the process receives SIGHUP
+#[cfg(unix)]
+fn reload_on_hangup(state: Arc<AppState>) -> Result<(), Box<dyn std::error::Error>> {
+    let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    tokio::spawn(async move {
+        while hangup.recv().await.is_some() {
+            info!("Received SIGHUP, reloading configuration");
+            if let Err(e) = state.reload().await {
+                error!("Failed to reload configuration: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
+
+async fn serve(
+    config: Config,
+    config_path: Option<PathBuf>,
+) -> Result<(), Box<dyn std::error::Error>> {
     // Create the app state
-    let mut app_state = AppState::new()?;
+    let mut app_state = AppState::new(&config, config_path)?;
 
-    // Start background metrics collection
-    app_state.start_background_metrics_collection()?;
+    // Start background metrics collection, in scrape mode metrics are collected by
+    // the metrics handler instead
+    if config.mode == Mode::Background {
+        app_state.start_background_metrics_collection()?;
+    }
 
     let app_state = Arc::new(app_state);
 
-    let app = Router::new()
-        .route("/", get(home))
+    #[cfg(unix)]
+    reload_on_hangup(Arc::clone(&app_state))?;
+
+    let mut app = Router::new()
+        .route("/", get(dashboard::index))
+        .route("/assets/{name}", get(dashboard::asset))
         .route("/metrics", get(metrics))
-        .with_state(app_state);
+        .route("/metrics/{subsystem}", get(subsystem_metrics))
+        .route("/api/v1/snapshot", get(api_snapshot))
+        .route("/api/v1/stream", get(api_stream))
+        .route("/api/v1/ws", get(api_websocket))
+        .route("/healthz", get(healthz))
+        .route("/ready", get(ready));
+    if config.admin {
+        app = app.route("/-/reload", post(reload));
+    }
+    let app = app.with_state(Arc::clone(&app_state));
+
+    // Run our app on every configured address, until a shutdown signal is received
+    let (shutdown_tx, _) = broadcast::channel(1);
+    let mut servers = Vec::new();
+    for address in &config.listen {
+        let listener = tokio::net::TcpListener::bind(address).await?;
+        println!("Listening on http://{}", address);
+        let mut shutdown_rx = shutdown_tx.subscribe();
+        servers.push(tokio::spawn(
+            axum::serve(listener, app.clone())
+                .with_graceful_shutdown(async move {
+                    let _ = shutdown_rx.recv().await;
+                })
+                .into_future(),
+        ));
+    }
+
+    shutdown_signal().await?;
+    info!("Shutting down, waiting for in-flight requests");
+    app_state.close_streams();
+    let _ = shutdown_tx.send(());
+    for server in servers {
+        server.await??;
+    }
+
+    app_state.stop_background_metrics_collection().await;
+    info!("Shutdown complete");
+    Ok(())
+}
+
+/// Waits for SIGINT, or SIGTERM on Unix
+async fn shutdown_signal() -> Result<(), Box<dyn std::error::Error>> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
+
+    Ok(())
+}
+
+async fn once(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    let app_state = AppState::new(&config, None)?;
+
+    // CPU usage is computed from the difference between two refreshes
+    app_state.collect_now().await;
+    tokio::time::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL).await;
+    app_state.collect_now().await;
+
+    let snapshot = app_state.snapshot();
+    let (buffer, _) = exposition::encode(Format::Text, &snapshot.families, &snapshot.created)?;
+    std::io::stdout().write_all(&buffer)?;
+    Ok(())
+}
 
-    // Run our app
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:9184").await?;
-    println!("Listening on http://0.0.0.0:9184");
-    axum::serve(listener, app).await?;
+fn check_config(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    // Creating the state also validates the collector options
+    AppState::new(&config, None)?;
 
-    // Background task will be cleaned up when the process terminates
+    println!("Configuration OK");
+    println!("  listen: {}", config.listen.join(", "));
+    println!("  mode: {:?}", config.mode);
+    println!("  interval: {}s", config.interval);
     Ok(())
 }
+
+fn list_collectors(config: &Config) {
+    for name in collectors::NAMES {
+        let status = if config.is_enabled(name) {
+            "enabled"
+        } else {
+            "disabled"
+        };
+        println!(
+            "{:<12} {:<8} every {}s",
+            name,
+            status,
+            config.interval(name).as_secs()
+        );
+    }
+}
+
+/// How long exit waits for collectors still running on blocking threads
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(1);
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let cli = Cli::parse();
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let result = runtime.block_on(run(cli));
+    // Don't wait forever for a collector that hung past its timeout
+    runtime.shutdown_timeout(SHUTDOWN_TIMEOUT);
+    result
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    // Initialize tracing
+    tracing_subscriber::fmt()
+        .with_max_level(cli.log_level)
+        .init();
+
+    let command = cli.command.unwrap_or(Command::Serve);
+    if let Command::Version = command {
+        println!("simon {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+
+    // Load the configuration
+    let config = Config::load(cli.config.as_deref())?;
+
+    match command {
+        Command::Serve => serve(config, cli.config).await,
+        Command::Once => once(config).await,
+        Command::CheckConfig => check_config(config),
+        Command::ListCollectors => {
+            list_collectors(&config);
+            Ok(())
+        }
+        Command::Version => unreachable!(),
+    }
+}
@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+
+/// Content type of the OpenMetrics text format
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Units recognised from the suffix of a metric family name
+const UNITS: &[&str] = &["seconds", "bytes", "ratio", "celsius", "percentage"];
+
+/// Creation time of every counter and histogram series, exported as `_created`.
+///
+/// The Prometheus client doesn't track creation times, so a series is considered
+/// created when it first shows up in a snapshot.
+#[derive(Debug, Clone, Default)]
+pub struct CreatedTimes {
+    times: HashMap<String, f64>,
+}
+
+impl CreatedTimes {
+    /// Records the series seen for the first time at `now` (Unix time in seconds) and
+    /// forgets the series that disappeared
+    pub fn update(&mut self, families: &[MetricFamily], now: f64) {
+        let mut times = HashMap::new();
+        for family in families {
+            if !has_created(family.get_field_type()) {
+                continue;
+            }
+            for metric in family.get_metric() {
+                let key = series_key(family.name(), metric.get_label());
+                let created = self.times.get(&key).copied().unwrap_or(now);
+                times.insert(key, created);
+            }
+        }
+        self.times = times;
+    }
+
+    fn get(&self, name: &str, labels: &[LabelPair]) -> Option<f64> {
+        self.times.get(&series_key(name, labels)).copied()
+    }
+}
+
+/// Unit of a metric, from the suffix of its name without `_total`
+pub fn unit(name: &str) -> Option<&'static str> {
+    UNITS
+        .iter()
+        .find(|unit| name.ends_with(&format!("_{}", unit)))
+        .copied()
+}
+
+fn has_created(metric_type: MetricType) -> bool {
+    matches!(
+        metric_type,
+        MetricType::COUNTER | MetricType::HISTOGRAM | MetricType::SUMMARY
+    )
+}
+
+fn series_key(name: &str, labels: &[LabelPair]) -> String {
+    let mut key = name.to_string();
+    for label in labels {
+        key.push('\0');
+        key.push_str(label.name());
+        key.push('=');
+        key.push_str(label.value());
+    }
+    key
+}
+
+/// Encodes metric families in the OpenMetrics text format, ending with `# EOF`
+pub fn encode(
+    families: &[MetricFamily],
+    created: &CreatedTimes,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    for family in families {
+        let metric_type = family.get_field_type();
+        // Counter families are named without their `_total` suffix
+        let name = match metric_type {
+            MetricType::COUNTER => family
+                .name()
+                .strip_suffix("_total")
+                .unwrap_or(family.name()),
+            _ => family.name(),
+        };
+        let type_name = match metric_type {
+            MetricType::COUNTER => "counter",
+            MetricType::GAUGE => "gauge",
+            MetricType::HISTOGRAM => "histogram",
+            MetricType::SUMMARY => "summary",
+            MetricType::UNTYPED => "unknown",
+        };
+
+        writeln!(writer, "# TYPE {} {}", name, type_name)?;
+        if let Some(unit) = unit(name) {
+            writeln!(writer, "# UNIT {} {}", name, unit)?;
+        }
+        if !family.help().is_empty() {
+            writeln!(writer, "# HELP {} {}", name, escape(family.help()))?;
+        }
+
+        for metric in family.get_metric() {
+            let labels = metric.get_label();
+            match metric_type {
+                MetricType::COUNTER => {
+                    let value = metric.get_counter().value();
+                    write_sample(writer, name, "_total", labels, None, value)?;
+                }
+                MetricType::GAUGE => {
+                    write_sample(writer, name, "", labels, None, metric.get_gauge().value())?;
+                }
+                MetricType::UNTYPED => {
+                    write_sample(writer, name, "", labels, None, metric.untyped.value())?;
+                }
+                MetricType::HISTOGRAM => write_histogram(writer, name, metric)?,
+                MetricType::SUMMARY => write_summary(writer, name, metric)?,
+            }
+            if has_created(metric_type) {
+                if let Some(created) = created.get(family.name(), labels) {
+                    write_sample(writer, name, "_created", labels, None, created)?;
+                }
+            }
+        }
+    }
+
+    writeln!(writer, "# EOF")
+}
+
+fn write_histogram(writer: &mut dyn Write, name: &str, metric: &Metric) -> io::Result<()> {
+    let labels = metric.get_label();
+    let histogram = metric.get_histogram();
+    let mut has_infinity = false;
+    for bucket in histogram.get_bucket() {
+        has_infinity |= bucket.upper_bound().is_infinite();
+        let le = format_value(bucket.upper_bound());
+        let value = bucket.cumulative_count() as f64;
+        write_sample(writer, name, "_bucket", labels, Some(("le", &le)), value)?;
+    }
+    // The +Inf bucket is mandatory
+    let count = histogram.get_sample_count() as f64;
+    if !has_infinity {
+        write_sample(writer, name, "_bucket", labels, Some(("le", "+Inf")), count)?;
+    }
+    write_sample(writer, name, "_count", labels, None, count)?;
+    write_sample(
+        writer,
+        name,
+        "_sum",
+        labels,
+        None,
+        histogram.get_sample_sum(),
+    )
+}
+
+fn write_summary(writer: &mut dyn Write, name: &str, metric: &Metric) -> io::Result<()> {
+    let labels = metric.get_label();
+    let summary = metric.get_summary();
+    for quantile in summary.get_quantile() {
+        let q = format_value(quantile.quantile());
+        write_sample(
+            writer,
+            name,
+            "",
+            labels,
+            Some(("quantile", &q)),
+            quantile.value(),
+        )?;
+    }
+    write_sample(
+        writer,
+        name,
+        "_count",
+        labels,
+        None,
+        summary.sample_count() as f64,
+    )?;
+    write_sample(writer, name, "_sum", labels, None, summary.sample_sum())
+}
+
+fn write_sample(
+    writer: &mut dyn Write,
+    name: &str,
+    suffix: &str,
+    labels: &[LabelPair],
+    extra: Option<(&str, &str)>,
+    value: f64,
+) -> io::Result<()> {
+    write!(writer, "{}{}", name, suffix)?;
+
+    let extra = extra.iter().map(|(name, value)| (*name, *value));
+    let mut labels = labels
+        .iter()
+        .map(|label| (label.name(), label.value()))
+        .chain(extra)
+        .peekable();
+    if labels.peek().is_some() {
+        let labels: Vec<String> = labels
+            .map(|(name, value)| format!("{}=\"{}\"", name, escape(value)))
+            .collect();
+        write!(writer, "{{{}}}", labels.join(","))?;
+    }
+
+    writeln!(writer, " {}", format_value(value))
+}
+
+/// Escapes backslashes, double quotes and new lines in label values and help texts
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{CounterVec, Gauge, Histogram, HistogramOpts, Opts, Registry};
+
+    use super::*;
+
+    fn registry() -> Registry {
+        let registry = Registry::new();
+
+        let requests = CounterVec::new(
+            Opts::new("requests_total", "Requests \\ by \"path\"\nand method").namespace("simon"),
+            &["path"],
+        )
+        .unwrap();
+        requests
+            .with_label_values(&["/a \"quoted\" \\ path\n"])
+            .inc_by(3.0);
+        registry.register(Box::new(requests)).unwrap();
+
+        let size = Gauge::with_opts(Opts::new("size_bytes", "Size").namespace("simon")).unwrap();
+        size.set(1.5);
+        registry.register(Box::new(size)).unwrap();
+
+        let duration = Histogram::with_opts(
+            HistogramOpts::new("duration_seconds", "Duration")
+                .namespace("simon")
+                .buckets(vec![0.5, 1.0]),
+        )
+        .unwrap();
+        duration.observe(0.25);
+        duration.observe(2.0);
+        registry.register(Box::new(duration)).unwrap();
+
+        registry
+    }
+
+    fn encode_to_string(families: &[MetricFamily], created: &CreatedTimes) -> String {
+        let mut buffer = Vec::new();
+        encode(families, created, &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn encodes_every_family_type() {
+        let families = registry().gather();
+        let mut created = CreatedTimes::default();
+        created.update(&families, 1700000000.0);
+
+        let expected = r#"# TYPE simon_duration_seconds histogram
+# UNIT simon_duration_seconds seconds
+# HELP simon_duration_seconds Duration
+simon_duration_seconds_bucket{le="0.5"} 1
+simon_duration_seconds_bucket{le="1"} 1
+simon_duration_seconds_bucket{le="+Inf"} 2
+simon_duration_seconds_count 2
+simon_duration_seconds_sum 2.25
+simon_duration_seconds_created 1700000000
+# TYPE simon_requests counter
+# HELP simon_requests Requests \\ by \"path\"\nand method
+simon_requests_total{path="/a \"quoted\" \\ path\n"} 3
+simon_requests_created{path="/a \"quoted\" \\ path\n"} 1700000000
+# TYPE simon_size_bytes gauge
+# UNIT simon_size_bytes bytes
+# HELP simon_size_bytes Size
+simon_size_bytes 1.5
+# EOF
+"#;
+        assert_eq!(encode_to_string(&families, &created), expected);
+    }
+
+    #[test]
+    fn keeps_creation_times_of_known_series() {
+        let registry = registry();
+        let mut created = CreatedTimes::default();
+        created.update(&registry.gather(), 1700000000.0);
+        created.update(&registry.gather(), 1700000060.0);
+
+        let output = encode_to_string(&registry.gather(), &created);
+        assert!(output.contains("simon_duration_seconds_created 1700000000\n"));
+        assert!(!output.contains("1700000060"));
+    }
+
+    #[test]
+    fn ends_with_eof_without_families() {
+        assert_eq!(encode_to_string(&[], &CreatedTimes::default()), "# EOF\n");
+    }
+
+    #[test]
+    fn recognises_units_from_the_name() {
+        assert_eq!(unit("simon_cpu_seconds"), Some("seconds"));
+        assert_eq!(unit("simon_memory_used_bytes"), Some("bytes"));
+        assert_eq!(unit("simon_network_received"), None);
+        // The unit must be a whole suffix
+        assert_eq!(unit("simon_kilobytes"), None);
+    }
+}
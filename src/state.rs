@@ -1,44 +1,311 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use arc_swap::ArcSwap;
+use prometheus::proto::MetricFamily;
 use prometheus::Registry;
-use sysinfo::{Networks, System};
-use tokio::sync::broadcast;
-use tokio::task::JoinHandle;
-use tracing::{debug, error, info};
+use tokio::sync::{broadcast, watch};
+use tokio::task::{JoinHandle, JoinSet};
+use tracing::{debug, error, info, warn};
 
-use crate::metrics::Metrics;
+use crate::collectors::{Collectors, Completion};
+use crate::config::{Config, Mode};
+use crate::exporter::ExporterMetrics;
+use crate::exposition::Filter;
+use crate::openmetrics::CreatedTimes;
+
+/// How long the background task waits when no collector is enabled
+const IDLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Number of collection intervals after which the last collection is stale
+const STALE_INTERVALS: u32 = 3;
+
+/// Number of snapshots kept for a stream subscriber that falls behind
+const STREAM_CAPACITY: usize = 4;
+
+/// The metrics gathered at the end of a collection cycle
+#[derive(Default)]
+pub struct Snapshot {
+    pub families: Vec<MetricFamily>,
+    /// When the cycle completed, `None` before the first collection
+    pub collected_at: Option<Instant>,
+    /// When a collector last succeeded, `None` until one has
+    pub succeeded_at: Option<Instant>,
+    /// Creation times of the counter series, for OpenMetrics
+    pub created: CreatedTimes,
+    /// Name of the collector exporting each metric family, simon's own metrics
+    /// have none
+    pub owners: Arc<HashMap<String, &'static str>>,
+    /// Collectors that ran in the cycle
+    pub updated: Vec<&'static str>,
+}
+
+impl Snapshot {
+    /// The metric families selected by a scrape filter. simon's own metrics are
+    /// included unless the filter is for a single subsystem.
+    pub fn select(&self, filter: &Filter) -> Cow<'_, [MetricFamily]> {
+        if filter.is_empty() {
+            return Cow::Borrowed(&self.families);
+        }
+        Cow::Owned(
+            self.families
+                .iter()
+                .filter(|family| {
+                    self.owners
+                        .get(family.name())
+                        .map_or(filter.includes_exporter(), |collector| {
+                            filter.includes(collector)
+                        })
+                })
+                .cloned()
+                .collect(),
+        )
+    }
+}
 
 pub struct AppState {
+    /// Holds simon's own metrics, collectors have a registry each
     pub(crate) registry: Registry,
-    pub(crate) metrics: Arc<Metrics>,
-    pub(crate) system: Arc<Mutex<System>>,
-    pub(crate) networks: Arc<Mutex<Networks>>,
+    /// Held to start runs and to record how they ended, never while collectors run
+    /// on their blocking threads
+    pub(crate) collectors: Arc<tokio::sync::Mutex<Collectors>>,
+    pub(crate) exporter: Arc<ExporterMetrics>,
+    /// Latest complete snapshot, swapped in whole after every cycle so scrapes
+    /// never see a half-updated cycle nor wait for the collectors
+    snapshot: Arc<ArcSwap<Snapshot>>,
+    /// Configuration in effect, replaced on reload
+    config: Mutex<Config>,
+    /// File the configuration is reloaded from
+    config_path: Option<PathBuf>,
     shutdown_tx: Option<broadcast::Sender<()>>,
-    _background_task: Option<JoinHandle<()>>,
+    /// Every published snapshot, for the stream subscribers
+    cycles: broadcast::Sender<Arc<Snapshot>>,
+    /// Changes whenever runs have been recorded, for the scrapes waiting on runs
+    /// that other scrapes started
+    finished: watch::Sender<()>,
+    /// Set on shutdown, ends the streams so the servers can stop
+    closing: watch::Sender<bool>,
+    background_task: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl AppState {
-    pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
+    pub fn new(
+        config: &Config,
+        config_path: Option<PathBuf>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
         let registry = Registry::new();
-        let metrics = Arc::new(Metrics::new(&registry)?);
-        let system = Arc::new(Mutex::new(System::new_all()));
-        let networks = Arc::new(Mutex::new(Networks::new_with_refreshed_list()));
+        let exporter = Arc::new(ExporterMetrics::new(&registry)?);
+        let collectors = Arc::new(tokio::sync::Mutex::new(Collectors::new(
+            config,
+            Arc::clone(&exporter),
+        )?));
 
         Ok(Self {
             registry,
-            metrics,
-            system,
-            networks,
+            collectors,
+            exporter,
+            snapshot: Arc::new(ArcSwap::from_pointee(Snapshot::default())),
+            config: Mutex::new(config.clone()),
+            config_path,
             shutdown_tx: None,
-            _background_task: None,
+            cycles: broadcast::channel(STREAM_CAPACITY).0,
+            finished: watch::channel(()).0,
+            closing: watch::channel(false).0,
+            background_task: Mutex::new(None),
         })
     }
 
+    /// Size in bytes from which responses are compressed
+    pub fn compression_min_size(&self) -> usize {
+        self.config
+            .lock()
+            .map(|config| config.compression_min_size)
+            .unwrap_or(usize::MAX)
+    }
+
+    /// In scrape mode, how often the streams collect the collectors they follow,
+    /// `None` in background mode
+    pub fn scrape_interval(&self) -> Option<Duration> {
+        let config = self.config.lock().ok()?;
+        (config.mode == Mode::Scrape).then(|| Duration::from_secs(config.interval))
+    }
+
+    /// The metrics of the last complete collection cycle
+    pub fn snapshot(&self) -> Arc<Snapshot> {
+        self.snapshot.load_full()
+    }
+
+    /// Receives every snapshot published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Snapshot>> {
+        self.cycles.subscribe()
+    }
+
+    /// Changes to `true` once the streams must end
+    pub fn closing(&self) -> watch::Receiver<bool> {
+        self.closing.subscribe()
+    }
+
+    /// Ends every stream, which would otherwise keep the servers from shutting down
+    pub fn close_streams(&self) {
+        self.closing.send_replace(true);
+    }
+
+    /// Checks that metrics are fresh, returning why they are not otherwise.
+    ///
+    /// In background mode the background task must be running and some collector
+    /// must have succeeded within a few intervals of the slowest collector. A single
+    /// collector that fails or hangs doesn't make simon unready, it shows in
+    /// `simon_exporter_collector_up` instead.
+    pub fn readiness(&self) -> Result<(), String> {
+        let (mode, stale_after) = match self.config.lock() {
+            Ok(config) => (config.mode, config.longest_interval() * STALE_INTERVALS),
+            Err(_) => return Err("Failed to acquire lock for the configuration".to_string()),
+        };
+        if mode != Mode::Background {
+            return Ok(());
+        }
+
+        let stopped = match self.background_task.lock() {
+            Ok(task) => task.as_ref().is_none_or(|task| task.is_finished()),
+            Err(_) => true,
+        };
+        if stopped {
+            return Err("Background collection is not running".to_string());
+        }
+
+        // Runs that all fail or time out still complete, only successes count
+        match self.snapshot.load().succeeded_at {
+            None => Err("No collection has succeeded yet".to_string()),
+            Some(succeeded_at) if succeeded_at.elapsed() > stale_after => Err(format!(
+                "Last successful collection completed {}s ago",
+                succeeded_at.elapsed().as_secs()
+            )),
+            Some(_) => Ok(()),
+        }
+    }
+
+    /// Runs every enabled collector right away and waits for all of them to finish
+    /// or time out
+    pub async fn collect_now(&self) {
+        let mut runs = JoinSet::new();
+        self.collectors
+            .lock()
+            .await
+            .start_all(Instant::now(), &mut runs);
+        self.finish(runs).await;
+    }
+
+    /// In scrape mode, runs the collectors kept by `filter`, except those that ran
+    /// more recently than the configured minimum age, so rapid scrapes share a run.
+    /// Waits as well for the runs of those collectors that other scrapes started,
+    /// so concurrent scrapes share one snapshot. Does nothing in background mode.
+    pub async fn collect_for_scrape(
+        &self,
+        filter: &Filter,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let (mode, min_age) = match self.config.lock() {
+            Ok(config) => (config.mode, Duration::from_secs(config.min_age)),
+            Err(_) => return Err("Failed to acquire lock for the configuration".into()),
+        };
+        if mode != Mode::Scrape {
+            return Ok(());
+        }
+
+        // Subscribed before looking at the runs so that no recording is missed
+        let mut finished = self.finished.subscribe();
+        let mut runs = JoinSet::new();
+        self.collectors
+            .lock()
+            .await
+            .start_stale(Instant::now(), filter, min_age, &mut runs);
+        let started = !runs.is_empty();
+        if started {
+            self.finish(runs).await;
+        }
+
+        let mut waited = false;
+        while self.collectors.lock().await.is_running(filter) {
+            waited = true;
+            if finished.changed().await.is_err() {
+                break;
+            }
+        }
+        if !started && !waited {
+            debug!("Serving metrics from the previous collection");
+        }
+        Ok(())
+    }
+
+    /// Waits for runs to finish or time out, publishing them as they end. The runs
+    /// are finished by a task of their own, so they are recorded even when the
+    /// request waiting for them goes away.
+    async fn finish(&self, runs: JoinSet<Completion>) {
+        let _ = tokio::spawn(self.publisher().finish_all(runs)).await;
+    }
+
+    fn publisher(&self) -> Publisher {
+        Publisher {
+            registry: self.registry.clone(),
+            collectors: Arc::clone(&self.collectors),
+            exporter: Arc::clone(&self.exporter),
+            snapshot: Arc::clone(&self.snapshot),
+            cycles: self.cycles.clone(),
+            finished: self.finished.clone(),
+        }
+    }
+
+    /// Reloads the configuration file and applies it to the running collectors.
+    ///
+    /// Collectors are reconfigured in place so their counters carry on. When the new
+    /// configuration is invalid, the error is returned and the old one stays in effect.
+    /// The listen addresses, the mode and whether the admin endpoints are served only
+    /// change on restart, until then the running ones are kept.
+    pub async fn reload(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let mut new_config = Config::load(self.config_path.as_deref())?;
+
+        let mut collectors = self.collectors.lock().await;
+        collectors.reconfigure(&new_config).await?;
+
+        let mut config = self
+            .config
+            .lock()
+            .map_err(|_| "Failed to acquire lock for the configuration")?;
+        if new_config.listen != config.listen || new_config.mode != config.mode {
+            warn!("Changes to the listen addresses or the mode take effect on restart");
+            new_config.listen = config.listen.clone();
+            new_config.mode = config.mode;
+        }
+        if new_config.admin != config.admin {
+            warn!("Enabling or disabling the admin endpoints takes effect on restart");
+            new_config.admin = config.admin;
+        }
+        *config = new_config;
+        drop(config);
+
+        // Drop the metrics of collectors that were just disabled from the snapshot
+        let families = gather(&self.registry, &collectors);
+        self.exporter.update_series(&families);
+        let previous = self.snapshot.load();
+        self.snapshot.store(Arc::new(Snapshot {
+            families,
+            collected_at: previous.collected_at,
+            succeeded_at: collectors.last_success(),
+            created: previous.created.clone(),
+            owners: collectors.owners(),
+            updated: Vec::new(),
+        }));
+
+        info!("Configuration reloaded");
+        Ok(())
+    }
+
     pub fn start_background_metrics_collection(
         &mut self,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        if self._background_task.is_some() {
+        if self.shutdown_tx.is_some() {
             return Err("Background metrics collection already started".into());
         }
 
@@ -47,56 +314,36 @@ impl AppState {
 
         // Spawn background metrics collection task
         let background_task = {
-            let metrics = Arc::clone(&self.metrics);
-            let system = Arc::clone(&self.system);
-            let networks = Arc::clone(&self.networks);
+            let publisher = self.publisher();
             let mut shutdown_rx = shutdown_rx;
 
             tokio::spawn(async move {
                 info!("Background metrics collection task started");
 
+                // Runs in progress, every collector is rescheduled when its run
+                // ends, so a slow one never holds up the others
+                let mut runs = JoinSet::new();
                 loop {
-                    // Check for shutdown signal
-                    match shutdown_rx.try_recv() {
-                        Ok(_) => {
+                    let next_due = {
+                        let mut collectors = publisher.collectors.lock().await;
+                        collectors.start_due(Instant::now(), &mut runs);
+                        collectors.next_due()
+                    };
+
+                    // Wait for the next collector to be due or for runs to end, or stop
+                    // right away on shutdown
+                    let next_due = next_due.unwrap_or_else(|| Instant::now() + IDLE_INTERVAL);
+                    tokio::select! {
+                        _ = shutdown_rx.recv() => {
                             info!("Background metrics task received shutdown signal");
                             break;
                         }
-                        Err(broadcast::error::TryRecvError::Empty) => {
-                            // No shutdown signal, continue
-                        }
-                        Err(broadcast::error::TryRecvError::Closed) => {
-                            info!("Shutdown channel closed, stopping background task");
-                            break;
-                        }
-                        Err(broadcast::error::TryRecvError::Lagged(_)) => {
-                            // Lagged behind, but continue
-                            debug!("Background task lagged behind shutdown signals");
-                        }
-                    }
-
-                    // Update system metrics
-                    if let Ok(mut sys) = system.lock() {
-                        sys.refresh_all();
-                        metrics.update_system_metrics(sys);
-                    } else {
-                        error!("Failed to acquire system lock for metrics update");
-                    }
-
-                    // Update network metrics
-                    if let Ok(mut nets) = networks.lock() {
-                        nets.refresh(false);
-                        for (name, network) in nets.iter() {
-                            metrics.update_network_metrics(name, network);
+                        Some(completion) = runs.join_next() => {
+                            publisher.finish(completion, &mut runs).await;
+                            debug!("Background metrics update completed");
                         }
-                    } else {
-                        error!("Failed to acquire networks lock for metrics update");
+                        _ = tokio::time::sleep_until(next_due.into()) => {}
                     }
-
-                    debug!("Background metrics update completed");
-
-                    // Sleep for 5 seconds
-                    tokio::time::sleep(Duration::from_secs(5)).await;
                 }
 
                 info!("Background metrics collection task stopped");
@@ -104,20 +351,177 @@ impl AppState {
         };
 
         self.shutdown_tx = Some(shutdown_tx);
-        self._background_task = Some(background_task);
+        self.background_task = Mutex::new(Some(background_task));
 
         Ok(())
     }
 
-    pub async fn stop_background_metrics_collection(&mut self) {
+    /// Stops the background task. Runs in progress are abandoned, their blocking
+    /// threads are left to the runtime shutdown.
+    pub async fn stop_background_metrics_collection(&self) {
         if let Some(shutdown_tx) = &self.shutdown_tx {
             let _ = shutdown_tx.send(());
         }
 
-        if let Some(task) = self._background_task.take() {
+        let task = match self.background_task.lock() {
+            Ok(mut task) => task.take(),
+            Err(_) => None,
+        };
+        if let Some(task) = task {
             let _ = task.await;
         }
+    }
+}
+
+/// The completion of a run, `None` when the task reporting it failed
+fn completed(result: Result<Completion, tokio::task::JoinError>) -> Option<Completion> {
+    match result {
+        Ok(completion) => Some(completion),
+        Err(e) => {
+            error!("Collector run failed: {}", e);
+            None
+        }
+    }
+}
+
+/// Records finished runs and publishes the snapshot, for the background task and
+/// the tasks finishing scrape-time runs
+struct Publisher {
+    registry: Registry,
+    collectors: Arc<tokio::sync::Mutex<Collectors>>,
+    exporter: Arc<ExporterMetrics>,
+    snapshot: Arc<ArcSwap<Snapshot>>,
+    cycles: broadcast::Sender<Arc<Snapshot>>,
+    finished: watch::Sender<()>,
+}
+
+impl Publisher {
+    /// Waits for every run to finish or time out
+    async fn finish_all(self, mut runs: JoinSet<Completion>) {
+        while let Some(completion) = runs.join_next().await {
+            self.finish(completion, &mut runs).await;
+        }
+    }
+
+    /// Records a run that ended, together with the other runs that ended already,
+    /// and publishes them as one snapshot
+    async fn finish(
+        &self,
+        completion: Result<Completion, tokio::task::JoinError>,
+        runs: &mut JoinSet<Completion>,
+    ) {
+        let mut completions = vec![completion];
+        while let Some(completion) = runs.try_join_next() {
+            completions.push(completion);
+        }
+
+        let mut collectors = self.collectors.lock().await;
+        let now = Instant::now();
+        let updated = completions
+            .into_iter()
+            .filter_map(completed)
+            .map(|completion| collectors.finish(completion, now))
+            .collect();
+        self.publish(&collectors, updated);
+    }
+
+    /// Updates the exporter's own metrics, swaps in a snapshot of every metric and
+    /// sends it to the stream subscribers. Series counts are those of the previous
+    /// snapshot, as counting needs the gather.
+    fn publish(&self, collectors: &Collectors, updated: Vec<&'static str>) {
+        self.exporter.update_process();
+        let families = gather(&self.registry, collectors);
+        self.exporter.update_series(&families);
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let mut created = self.snapshot.load().created.clone();
+        created.update(&families, now.as_secs_f64());
+
+        let published = Arc::new(Snapshot {
+            families,
+            collected_at: Some(Instant::now()),
+            succeeded_at: collectors.last_success(),
+            created,
+            owners: collectors.owners(),
+            updated,
+        });
+        self.snapshot.store(Arc::clone(&published));
+        // Without subscribers there is nobody to send to
+        let _ = self.cycles.send(published);
+        self.finished.send_replace(());
+    }
+}
+
+/// Gathers simon's own metrics and those of every collector, sorted by name
+fn gather(registry: &Registry, collectors: &Collectors) -> Vec<MetricFamily> {
+    let mut families = registry.gather();
+    families.extend(collectors.gather());
+    families.sort_by(|a, b| a.name().cmp(b.name()));
+    families
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+
+    use super::*;
+    use crate::config::CollectorConfig;
+
+    /// Scrape mode with only the memory collector, which is quick to run
+    fn scrape_config() -> Config {
+        let mut config = Config {
+            mode: Mode::Scrape,
+            min_age: 0,
+            ..Config::default()
+        };
+        for name in crate::collectors::NAMES {
+            config.collectors.insert(
+                name.to_string(),
+                CollectorConfig {
+                    enabled: *name == "memory",
+                    interval: None,
+                    timeout: None,
+                    options: toml::Table::new(),
+                },
+            );
+        }
+        config
+    }
+
+    #[tokio::test]
+    async fn dropped_scrapes_still_finish_their_runs() {
+        let state = AppState::new(&scrape_config(), None).unwrap();
+        let mut cycles = state.subscribe();
+
+        // The scrape starts its runs on the first poll, then goes away like a
+        // client that disconnects
+        let filter = Filter::default();
+        assert!(state.collect_for_scrape(&filter).now_or_never().is_none());
+
+        let published = tokio::time::timeout(Duration::from_secs(5), cycles.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(published.updated, vec!["memory"]);
+
+        // The collector is no longer running, so the next scrape runs it again
+        state.collect_for_scrape(&filter).await.unwrap();
+        assert!(state.snapshot().collected_at > published.collected_at);
+    }
+
+    #[tokio::test]
+    async fn concurrent_scrapes_wait_for_the_same_runs() {
+        let state = AppState::new(&scrape_config(), None).unwrap();
+        let filter = Filter::default();
+
+        // A first scrape starts the run, the second one finds it running
+        assert!(state.collect_for_scrape(&filter).now_or_never().is_none());
+        state.collect_for_scrape(&filter).await.unwrap();
 
-        self.shutdown_tx = None;
+        let snapshot = state.snapshot();
+        assert!(snapshot.collected_at.is_some());
+        assert_eq!(snapshot.updated, vec!["memory"]);
     }
 }